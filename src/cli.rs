@@ -0,0 +1,175 @@
+use std::path::Path;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::retention::RetentionPolicy;
+use crate::sqlite::SqliteStorage;
+use crate::storage::{JsonStorage, Storage};
+
+#[derive(Debug, Parser)]
+#[command(name = "syndication_junction", version, about = "Combines a bunch of RSS and Atom feeds into a single unified feed")]
+pub struct Cli {
+  #[command(subcommand)]
+  pub command: Commands,
+  /// How many seconds to wait for another run to release its lock on the db
+  #[arg(long, global = true, value_name = "SECONDS", default_value_t = 30, env = "SYNDICATION_JUNCTION_LOCK_TIMEOUT")]
+  pub lock_timeout: u64,
+  /// Where the db is stored
+  #[arg(long, global = true, value_enum, default_value_t = StorageKind::Json, env = "SYNDICATION_JUNCTION_STORAGE")]
+  pub storage: StorageKind,
+  /// The db file [default: `db.json`, or `db.sqlite` with `--storage sqlite`]
+  #[arg(long, global = true, value_name = "PATH", env = "SYNDICATION_JUNCTION_DB")]
+  pub db: Option<String>,
+  /// The config file [default: `config.toml` next to the db]
+  #[arg(long, global = true, value_name = "PATH", env = "SYNDICATION_JUNCTION_CONFIG")]
+  pub config: Option<String>,
+  /// Where downloaded media is saved by `output-rss <FILE> <HOST_NAME>`
+  #[arg(long, global = true, value_name = "DIR", default_value = "media", env = "SYNDICATION_JUNCTION_MEDIA_DIR")]
+  pub media_dir: String
+}
+
+impl Cli {
+  pub fn db_path(&self) -> String {
+    match &self.db {
+      Some(db) => db.clone(),
+      None => self.storage.default_path().to_string()
+    }
+  }
+  pub fn config_path(&self) -> String {
+    match &self.config {
+      Some(config) => config.clone(),
+      None => {
+        // keep the config with the db so every aggregation on a host has its own
+        let db_path = self.db_path();
+        match Path::new(&db_path).parent() {
+          Some(parent) => parent.join("config.toml").to_string_lossy().to_string(),
+          None => String::from("config.toml")
+        }
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum StorageKind {
+  /// Everything in one JSON file, `db.json`
+  Json,
+  /// An embedded SQLite database, `db.sqlite`
+  Sqlite
+}
+
+impl StorageKind {
+  pub fn default_path(&self) -> &'static str {
+    match self {
+      StorageKind::Json => "db.json",
+      StorageKind::Sqlite => "db.sqlite"
+    }
+  }
+  pub fn open(&self, path: &str) -> Box<dyn Storage> {
+    match self {
+      StorageKind::Json => Box::new(JsonStorage { path: path.to_string() }),
+      StorageKind::Sqlite => Box::new(SqliteStorage { path: path.to_string() })
+    }
+  }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+  /// Fetches feeds and merges their items into the db
+  ///
+  /// With no URLs every subscribed feed is refreshed; URLs that aren't subscribed yet are added with default options
+  Fetch {
+    urls: Vec<String>,
+    /// Only refresh this subscribed feed (can be given more than once)
+    #[arg(long, value_name = "URL")]
+    only: Vec<String>,
+    /// Only refresh subscribed feeds with this tag (can be given more than once)
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>
+  },
+  /// Subscribes to a feed, or updates the options of a feed that is already subscribed
+  Add {
+    url: String,
+    /// Overrides the title of the feed
+    #[arg(long)]
+    title: Option<String>,
+    /// Overrides the link to the feed
+    #[arg(long)]
+    link: Option<String>,
+    /// A command the raw feed is piped into before it is parsed, EX: `sed s/something/something-else/`
+    #[arg(long)]
+    manipulate_input: Option<String>,
+    /// Which fetched items are kept: `keep_all`, `keep_last <COUNT>`, `keep_newer_than <DURATION>` or `mirror_upstream`
+    #[arg(long, value_name = "POLICY")]
+    retention: Option<RetentionPolicy>,
+    /// Tags the feed so it can be picked out with `fetch --tag` (can be given more than once)
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>
+  },
+  /// Unsubscribes from a feed and deletes its stored items
+  Remove {
+    url: String
+  },
+  /// Applies each feed's retention policy to the items already stored (this also happens after every fetch)
+  Prune {
+    /// Only prune these subscribed feeds
+    urls: Vec<String>,
+    /// List the items that would be pruned without removing them
+    #[arg(long)]
+    dry_run: bool
+  },
+  /// Lists every subscribed feed
+  List,
+  /// Shows the options and stored items of a feed
+  Show {
+    url: String
+  },
+  /// Shows how a stored item changed over time, newest change first
+  History {
+    guid: String
+  },
+  /// Converts the JSON db (`--db`, `db.json` by default) into a SQLite store, checking that nothing was lost along the way
+  Migrate {
+    /// Where to write the SQLite store [default: the db with a `.sqlite` extension]
+    #[arg(long, value_name = "PATH")]
+    to: Option<String>,
+    /// Replace the SQLite store if it already exists
+    #[arg(long)]
+    force: bool
+  },
+  /// Outputs the aggregated feed as RSS
+  OutputRss {
+    #[arg(default_value = "rss.xml")]
+    output_file_name: String,
+    /// Downloads all of the media in the feed to `--media-dir` and rewrites links to it to start with `<HOST_NAME>/media/`
+    host_name: Option<String>
+  },
+  /// Outputs the aggregated feed as Atom
+  OutputAtom {
+    #[arg(default_value = "atom.xml")]
+    output_file_name: String,
+    /// Downloads all of the media in the feed to `--media-dir` and rewrites links to it to start with `<HOST_NAME>/media/`;
+    /// the feed's `self` link is also `<HOST_NAME>/<OUTPUT_FILE_NAME>`
+    host_name: Option<String>
+  },
+  /// Outputs the aggregated feed as a JSON Feed
+  OutputJsonFeed {
+    #[arg(default_value = "feed.json")]
+    output_file_name: String,
+    /// Downloads all of the media in the feed to `--media-dir` and rewrites links to it to start with `<HOST_NAME>/media/`;
+    /// the feed's `feed_url` is also `<HOST_NAME>/<OUTPUT_FILE_NAME>`
+    host_name: Option<String>
+  },
+  /// Outputs the aggregated feed as a static HTML site: a paginated timeline and a page per item
+  OutputHtml {
+    #[arg(default_value = "site")]
+    output_dir: String,
+    /// Downloads all of the media in the feed to `--media-dir` and rewrites links to it to start with `<HOST_NAME>/media/`
+    host_name: Option<String>,
+    /// A directory with a `page.html` and/or `item.html` to use in place of the built in templates
+    #[arg(long)]
+    templates: Option<String>,
+    /// How many items each page of the timeline has
+    #[arg(long, default_value_t = 20)]
+    per_page: usize
+  }
+}
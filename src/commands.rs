@@ -0,0 +1,495 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::ConfigFile;
+use crate::fetch::{FetchOutcome, FetchRequest, fetch_all};
+use crate::helpers::download_image;
+use crate::html::{self, Templates};
+use crate::retention::RetentionPolicy;
+use crate::storage::Storage;
+use crate::structs::*;
+
+/// Works out which feeds `fetch` should refresh:
+/// - any URLs given directly are fetched (and subscribed to if they're new)
+/// - otherwise every feed in the db is fetched, narrowed down by `only` and `tags`,
+///   except the ones missing from the config file
+fn select_feeds(urls: Vec<String>, only: &[String], tags: &[String], db: &Db) -> Vec<String> {
+  if !urls.is_empty() {
+    return urls;
+  }
+  for url in only {
+    match db.rss.get(url) {
+      Some(options) if options.missing_from_config => {
+        log::warn!("⚠️ {url} isn't in the config file; add it back with `add` to fetch it again");
+      },
+      Some(_) => {},
+      None => {
+        log::warn!("⚠️ {url} is not a subscribed feed; pass it without --only to subscribe to it");
+      }
+    }
+  }
+  let mut feeds = db.rss.iter()
+    .filter(|(_, options)| !options.missing_from_config)
+    .filter(|(url, _)| only.is_empty() || only.contains(url))
+    .filter(|(_, options)| tags.is_empty() || options.settings.tags.iter().any(|tag| tags.contains(tag)))
+    .map(|(url, _)| url.clone())
+    .collect::<Vec<_>>();
+  feeds.sort();
+  feeds
+}
+
+// drops the items the feed's retention policy doesn't keep, returning whether there were any
+fn apply_retention(feed: &str, options: &mut FeedOptions, now: i64) -> bool {
+  let pruned = options.apply_retention(now);
+  if !pruned.is_empty() {
+    log::info!("🗑️ {feed}: pruned {} item(s) ({})", pruned.len(), options.settings.retention);
+  }
+  !pruned.is_empty()
+}
+
+pub async fn fetch(db: &mut Db, urls: Vec<String>, only: Vec<String>, tags: Vec<String>) {
+  // fetching a feed by its url subscribes to it again if it was taken out of the config file
+  for url in urls.iter() {
+    if let Some(options) = db.rss.get_mut(url) {
+      options.missing_from_config = false;
+    }
+  }
+  let feeds = select_feeds(urls, &only, &tags, db);
+  let requests = feeds.iter().map(|feed| FetchRequest::new(feed, db)).collect::<Vec<_>>();
+  let timeout = std::time::Duration::from_secs(db.settings.fetch_timeout);
+  let results = match fetch_all(requests, db.settings.max_concurrent_fetches, db.settings.max_concurrent_fetches_per_host, timeout).await {
+    Ok(results) => results,
+    Err(error) => {
+      log::error!("❌ {}", error);
+      return;
+    }
+  };
+  let now = chrono::Utc::now();
+  let fetched_at = now.to_rfc2822();
+  for (feed, result) in results {
+    match result {
+      Ok(FetchOutcome::NotModified) => {
+        log::info!("⏭️ {feed}: not modified since last fetch, skipping");
+        if let Some(options) = db.rss.get_mut(&feed) {
+          options.last_fetched = Some(fetched_at.clone());
+          options.last_error = None;
+          // items still age out with `keep_newer_than` while the feed itself stays the same
+          apply_retention(&feed, options, now.timestamp());
+        }
+      },
+      Ok(FetchOutcome::Updated { rss, encoding, etag, last_modified }) => {
+        let options = db.rss.entry(feed.clone()).or_default();
+        let report = options.merge(*rss, &fetched_at, db.settings.max_revisions_per_item);
+        log::info!("✅ {feed}: {report}");
+        // only remember the validators once the response has been parsed successfully
+        options.etag = etag;
+        options.last_modified = last_modified;
+        options.validators_manipulate_input = options.settings.manipulate_input.clone();
+        options.encoding = Some(encoding);
+        options.last_fetched = Some(fetched_at.clone());
+        options.last_error = None;
+        apply_retention(&feed, options, now.timestamp());
+      },
+      Err(err) => {
+        log::error!("❌ {feed}: {}", err);
+        if let Some(options) = db.rss.get_mut(&feed) {
+          options.last_fetched = Some(fetched_at.clone());
+          options.last_error = Some(err.to_string());
+        }
+      }
+    }
+  }
+}
+
+/// Applies each feed's retention policy to the items already stored, or with
+/// `dry_run` only lists what would be dropped; returns whether anything was dropped
+pub fn prune(db: &mut Db, urls: Vec<String>, dry_run: bool) -> bool {
+  let feeds = select_feeds(vec![], &urls, &[], db);
+  let now = chrono::Utc::now().timestamp();
+  let mut modified = false;
+  for feed in feeds {
+    let options = match db.rss.get_mut(&feed) {
+      Some(options) => options,
+      None => continue
+    };
+    if dry_run {
+      let pruned = options.items_to_prune(now);
+      println!("{feed} ({}): {} of {} item(s) would be pruned", options.settings.retention, pruned.len(), options.rss.channel.item.len());
+      for item in pruned {
+        println!("    {} {} ({})", item.pub_date.as_deref().unwrap_or(""), item.title.as_deref().unwrap_or(""), item.guid);
+      }
+    } else if apply_retention(&feed, options, now) {
+      modified = true;
+    }
+  }
+  modified
+}
+
+pub fn add(db: &mut Db, url: String, title: Option<String>, link: Option<String>, manipulate_input: Option<String>, retention: Option<RetentionPolicy>, tags: Vec<String>) {
+  let is_new = !matches!(db.rss.get(&url), Some(options) if !options.missing_from_config);
+  let options = db.rss.entry(url.clone()).or_default();
+  if let Some(title) = title {
+    options.settings.title = title;
+  }
+  if let Some(link) = link {
+    options.settings.link = link;
+  }
+  if let Some(manipulate_input) = manipulate_input {
+    options.settings.manipulate_input = manipulate_input;
+  }
+  if let Some(retention) = retention {
+    options.settings.retention = retention;
+  }
+  for tag in tags {
+    if !options.settings.tags.contains(&tag) {
+      options.settings.tags.push(tag);
+    }
+  }
+  options.missing_from_config = false;
+  options.forget_stale_validators();
+  if is_new {
+    log::info!("✅ Subscribed to {url}; run `fetch --only {url}` to fetch it");
+  } else {
+    log::info!("✅ Updated the options of {url}");
+  }
+}
+
+pub fn remove(db: &mut Db, url: &str) -> bool {
+  match db.rss.remove(url) {
+    Some(options) => {
+      log::info!("✅ Unsubscribed from {url} and removed {} stored item(s)", options.rss.channel.item.len());
+      true
+    },
+    None => {
+      log::error!("❌ {url} is not a subscribed feed");
+      false
+    }
+  }
+}
+
+pub fn list(db: &Db) {
+  let mut urls = db.rss.keys().collect::<Vec<_>>();
+  urls.sort();
+  for url in urls {
+    let options = &db.rss[url];
+    println!("{url}");
+    println!("  title: {}", options.title());
+    if options.missing_from_config {
+      println!("  not in the config file, so not fetched or output");
+    }
+    println!("  items: {}", options.rss.channel.item.len());
+    if !options.tombstones.is_empty() {
+      println!("  deleted from the feed: {}", options.tombstones.len());
+    }
+    println!("  last fetched: {}", options.last_fetched.as_deref().unwrap_or("never"));
+    if let Some(last_error) = &options.last_error {
+      println!("  last error: {last_error}");
+    }
+  }
+}
+
+pub fn show(db: &Db, url: &str) -> bool {
+  let options = match db.rss.get(url) {
+    Some(options) => options,
+    None => {
+      log::error!("❌ {url} is not a subscribed feed");
+      return false;
+    }
+  };
+  println!("{url}");
+  if options.missing_from_config {
+    println!("  not in the config file, so not fetched or output; add it back with `add` or delete it with `remove`");
+  }
+  println!("  title: {}", options.title());
+  println!("  link: {}", options.link());
+  println!("  tags: {}", options.settings.tags.join(", "));
+  println!("  manipulate input: {}", options.settings.manipulate_input);
+  println!("  retention: {}", options.settings.retention);
+  println!("  etag: {}", options.etag.as_deref().unwrap_or(""));
+  println!("  last modified: {}", options.last_modified.as_deref().unwrap_or(""));
+  println!("  encoding: {}", options.encoding.as_deref().unwrap_or(""));
+  println!("  last fetched: {}", options.last_fetched.as_deref().unwrap_or("never"));
+  println!("  last error: {}", options.last_error.as_deref().unwrap_or(""));
+  println!("  items: {}", options.rss.channel.item.len());
+  for item in options.rss.channel.item.iter() {
+    println!("    {} {} ({})", item.pub_date.as_deref().unwrap_or(""), item.title.as_deref().unwrap_or(""), item.guid);
+    if let Some(tombstoned_at) = options.tombstones.get(&item.guid) {
+      println!("      deleted from the feed as of {tombstoned_at}");
+    }
+  }
+  true
+}
+
+/// Prints the earlier versions of every stored item with the given guid
+pub fn history(db: &Db, guid: &str) -> bool {
+  let mut urls = db.rss.keys().collect::<Vec<_>>();
+  urls.sort();
+  let mut found = false;
+  for url in urls {
+    if let Some(item) = db.rss[url].rss.channel.item.get_by_guid(guid) {
+      found = true;
+      println!("{} ({url})", item.title.as_deref().unwrap_or(guid));
+      if item.revisions.is_empty() {
+        println!("  unchanged since it was first fetched");
+      }
+      for revision in item.revisions.iter().rev() {
+        println!("  changed by the fetch at {}:", revision.fetched_at);
+        for change in revision.changes.iter() {
+          println!("    {}:", change.field);
+          println!("      - {}", change.old.as_deref().unwrap_or("(none)"));
+          println!("      + {}", change.new.as_deref().unwrap_or("(none)"));
+        }
+      }
+    }
+  }
+  if !found {
+    log::error!("❌ No stored item has the guid {guid}");
+  }
+  found
+}
+
+/// Copies everything from one store into another and reads it back to make sure the copy is identical
+pub fn migrate(from: &dyn Storage, to: &dyn Storage, config_path: &str, force: bool) -> bool {
+  if std::path::Path::new(to.path()).exists() && !force {
+    log::error!("❌ {} already exists; pass --force to replace it", to.path());
+    return false;
+  }
+  let mut db = match from.load() {
+    Ok(db) => db,
+    Err(error) => {
+      log::error!("❌ {}", error);
+      return false;
+    }
+  };
+  // neither store keeps the settings, so a db from before the config file has its
+  // settings moved into the config file first instead of being lost
+  let config_file = match ConfigFile::load_or_migrate(config_path, &mut db) {
+    Ok(config_file) => config_file,
+    Err(error) => {
+      log::error!("❌ {}", error);
+      return false;
+    }
+  };
+  if let Err(error) = to.save(&db) {
+    log::error!("❌ {}", error);
+    return false;
+  }
+  let migrated = to.load().map(|mut migrated| {
+    config_file.apply(&mut migrated);
+    migrated
+  });
+  match migrated {
+    Ok(migrated) if migrated == db => {
+      let item_count = db.rss.values().map(|options| options.rss.channel.item.len()).sum::<usize>();
+      log::info!("✅ Migrated {} feed(s) and {} item(s) from {} to {}", db.rss.len(), item_count, from.path(), to.path());
+      true
+    },
+    Ok(_) => {
+      log::error!("❌ {} doesn't match {} after migrating; keep using {}", to.path(), from.path(), from.path());
+      false
+    },
+    Err(error) => {
+      log::error!("❌ {}", error);
+      false
+    }
+  }
+}
+
+/// Downloads the media of every stored item (attached or in its HTML) to `media_dir`
+/// and points the item at the copies under `<host_name>/media/`
+#[allow(clippy::single_match)]
+async fn mirror_media(db: &mut Db, host_name: &str, media_dir: &str) {
+  for (_, feed_options) in db.rss.iter_mut().filter(|(_, feed_options)| !feed_options.missing_from_config) {
+    for item in feed_options.rss.channel.item.iter_mut() {
+      match item.media_content.as_mut() {
+        Some(media_content) => {
+          for content_item in media_content.iter_mut() {
+            match download_image(&content_item.url, media_dir).await {
+              Ok(_) => {
+                content_item.url = content_item.url.replace("https://", &format!("{}/media/", &host_name));
+              },
+              Err(error) => {
+                log::error!("{}", error);
+              }
+            }
+          }
+        },
+        None => {}
+      }
+      match item.description.as_mut() {
+        Some(description) => {
+          let description_html_frag = scraper::Html::parse_fragment(description);
+          let images_selector = scraper::Selector::parse("img").unwrap();
+          let images = description_html_frag.select(&images_selector).collect::<Vec::<_>>();
+          for image in images {
+            match image.value().attr("src") {
+              Some(src) => {
+                match download_image(src, media_dir).await {
+                  Ok(_) => {
+                    *description = description.replace(src, &src.replace("https://", &format!("{}/media/", &host_name)).replace("%", "%25"));
+                  },
+                  Err(error) => {
+                    log::error!("{}", error);
+                  }
+                }
+              },
+              None => {}
+            };
+
+          }
+        },
+        None => {}
+      }
+      match item.content_encoded.as_mut() {
+        Some(description) => {
+          let description_html_frag = scraper::Html::parse_fragment(description);
+          let images_selector = scraper::Selector::parse("img").unwrap();
+          let images = description_html_frag.select(&images_selector).collect::<Vec::<_>>();
+          for image in images {
+            match image.value().attr("src") {
+              Some(src) => {
+                match download_image(src, media_dir).await {
+                  Ok(_) => {
+                    *description = description.replace(src, &src.replace("https://", &format!("{}/media/", &host_name)));
+                  },
+                  Err(error) => {
+                    log::error!("{}", error);
+                  }
+                }
+              },
+              None => {}
+            };
+
+          }
+        },
+        None => {}
+      }
+    }
+  }
+}
+
+fn write_output(output_file_name: &str, output: &str, format: &str) {
+  let mut f = match File::create(output_file_name) {
+    Ok(f) => f,
+    Err(error) => {
+      log::error!("❌ Error creating {}: {}", output_file_name, error);
+      return;
+    }
+  };
+  match write!(f, "{}", output) {
+    Ok(()) => {
+      log::info!("✅ Sucessfully wrote {} file {}", format, output_file_name);
+    },
+    Err(error) => {
+      log::error!("❌ {}", error);
+    }
+  }
+}
+
+/// The files an output command writes and the db each is made from: the combined feed
+/// and/or, with `output_one_channel` off, one per feed next to it named after the combined
+/// one (EX: `rss-art.xml` next to `rss.xml`), so the outputs of different formats can share a directory
+fn get_outputs(db: &Db, output_file_name: &str) -> Vec<(String, Db)> {
+  let mut outputs = vec![];
+  if db.settings.output_one_channel || db.settings.output_combined_feed {
+    outputs.push((output_file_name.to_string(), db.clone()));
+  }
+  if !db.settings.output_one_channel {
+    let path = Path::new(output_file_name);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    for (name, feed_db) in db.per_feed_outputs() {
+      let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, name, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, name)
+      };
+      outputs.push((path.with_file_name(file_name).to_string_lossy().to_string(), feed_db));
+    }
+  }
+  outputs
+}
+
+pub async fn output_rss(db: &mut Db, output_file_name: String, host_name: Option<String>, media_dir: &str) {
+  if let Some(host_name) = &host_name {
+    mirror_media(db, host_name, media_dir).await;
+  }
+  for (output_file_name, db) in get_outputs(db, &output_file_name) {
+    let rss_output = db.output_rss().expect("Failed outputing feed to RSS").replace("<content:encoded/>", "");
+    write_output(&output_file_name, &format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}", &rss_output), "RSS");
+  }
+}
+
+pub async fn output_atom(db: &mut Db, output_file_name: String, host_name: Option<String>, media_dir: &str) {
+  if let Some(host_name) = &host_name {
+    mirror_media(db, host_name, media_dir).await;
+  }
+  for (output_file_name, db) in get_outputs(db, &output_file_name) {
+    let self_link = get_self_link(host_name.as_deref(), &output_file_name);
+    let atom_output = db.output_atom(self_link, &output_file_name).expect("Failed outputing feed to Atom");
+    write_output(&output_file_name, &format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}", &atom_output), "Atom");
+  }
+}
+
+pub async fn output_json_feed(db: &mut Db, output_file_name: String, host_name: Option<String>, media_dir: &str) {
+  if let Some(host_name) = &host_name {
+    mirror_media(db, host_name, media_dir).await;
+  }
+  for (output_file_name, db) in get_outputs(db, &output_file_name) {
+    let feed_url = get_self_link(host_name.as_deref(), &output_file_name);
+    let json_feed_output = db.output_json_feed(feed_url).expect("Failed outputing feed to JSON Feed");
+    write_output(&output_file_name, &json_feed_output, "JSON Feed");
+  }
+}
+
+pub async fn output_html(db: &mut Db, output_dir: String, host_name: Option<String>, media_dir: &str, templates: Option<String>, per_page: usize) {
+  let templates = match Templates::load(templates.as_deref()) {
+    Ok(templates) => templates,
+    Err(error) => {
+      log::error!("❌ Error reading templates: {}", error);
+      return;
+    }
+  };
+  if let Some(host_name) = &host_name {
+    mirror_media(db, host_name, media_dir).await;
+  }
+  // the sites of single feeds go under `feeds/`, so they can't clash with the pages of the combined one
+  let mut sites = vec![];
+  if db.settings.output_one_channel || db.settings.output_combined_feed {
+    sites.push((PathBuf::from(&output_dir), db.clone()));
+  }
+  if !db.settings.output_one_channel {
+    for (name, feed_db) in db.per_feed_outputs() {
+      sites.push((Path::new(&output_dir).join("feeds").join(name), feed_db));
+    }
+  }
+  for (site_dir, db) in sites {
+    let files = db.output_html(&templates, per_page);
+    for (path, html) in files.iter() {
+      let path = site_dir.join(path);
+      let result = match path.parent() {
+        Some(parent) => std::fs::create_dir_all(parent).and_then(|_| std::fs::write(&path, html)),
+        None => std::fs::write(&path, html)
+      };
+      if let Err(error) = result {
+        log::error!("❌ Error writing {}: {}", path.display(), error);
+        return;
+      }
+    }
+    log::info!("✅ Sucessfully wrote {} HTML file(s) to {}", files.len(), site_dir.display());
+    for path in html::get_stale_files(&site_dir, &files) {
+      match std::fs::remove_file(&path) {
+        Ok(()) => log::info!("🗑️ Removed {}, which is no longer part of the site", path.display()),
+        Err(error) => log::error!("❌ Error removing {}: {}", path.display(), error)
+      }
+    }
+  }
+}
+
+// where an output file will be published, which is only known with a host name
+fn get_self_link(host_name: Option<&str>, output_file_name: &str) -> Option<String> {
+  let file_name = Path::new(output_file_name)
+    .file_name()
+    .map(|file_name| file_name.to_string_lossy().to_string())
+    .unwrap_or(output_file_name.to_string());
+  host_name.map(|host_name| format!("{}/{}", host_name.trim_end_matches('/'), file_name))
+}
@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use toml_edit::DocumentMut;
+
+use crate::migrations;
+use crate::structs::*;
+
+#[derive(Debug)]
+pub enum ConfigError {
+  FileReadError(std::io::Error),
+  TomlDeserializeError(toml::de::Error),
+  TomlDocumentError(toml_edit::TomlError),
+  FormattingError(toml::ser::Error),
+  DocumentFormattingError(toml_edit::ser::Error),
+  FileWriteError(std::io::Error)
+}
+
+impl std::fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        ConfigError::FileReadError(error) => write!(f, "Error reading config file: {}", error),
+        ConfigError::TomlDeserializeError(error) => write!(f, "Error deserializing TOML from config file: {}", error),
+        ConfigError::TomlDocumentError(error) => write!(f, "Error parsing config file: {}", error),
+        ConfigError::FormattingError(error) => write!(f, "Error formatting config file: {}", error),
+        ConfigError::DocumentFormattingError(error) => write!(f, "Error formatting config file: {}", error),
+        ConfigError::FileWriteError(error) => write!(f, "Error writing config file: {}", error)
+     }
+  }
+}
+
+/// Everything a person might want to edit by hand, kept apart from the
+/// machine written item store
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+  #[serde(flatten)]
+  pub settings: Settings,
+  pub feeds: BTreeMap<String, FeedSettings>
+}
+
+const HEADER: &str = "# syndication_junction config
+#
+# The feeds themselves (and every item ever fetched from them) are stored
+# separately; this file only holds the settings. Feeds are listed at the
+# bottom as `[feeds.\"<url>\"]` tables.
+";
+
+// the comment written above each setting when the config file is generated
+const SETTING_COMMENTS: &[(&str, &str)] = &[
+  ("output_one_channel", "Whether all feeds are output as one channel; when false every feed is also output\non its own next to it, EX: `rss-art.xml` for a feed named `art` by its `output_name` (or a slug of its title)"),
+  ("output_combined_feed", "Whether the combined feed is still output alongside the per feed ones when output_one_channel = false"),
+  ("title", "The title attached to the feed"),
+  ("link", "The link attached to the feed"),
+  ("include_description_as_title_if_none_given", "Whether or not to fill in empty titles from the description\n(mastodon leaves titles empty, which makes RSS readers look funny)"),
+  ("description_title_word_count", "How many words from the description to use as auto-title"),
+  ("title_ellipsis", "What to put after an auto-title"),
+  ("populate_content_encoded", "Whether or not to add a `content:encoded` element with the contents of the `description` element"),
+  ("add_media_to_content_encoded", "Whether or not to add attached media items to the `content:encoded` element"),
+  ("max_entries_published", "The amount of posts the output feed contains (-1 is all posts)"),
+  ("override_item_author", "Whether or not to override entry authors with the feed's author"),
+  ("max_concurrent_fetches", "How many feeds are fetched at the same time"),
+  ("max_concurrent_fetches_per_host", "How many feeds are fetched at the same time from any single host"),
+  ("fetch_timeout", "How many seconds a single fetch may take (connecting included) before it is given up on"),
+  ("db_backups", "How many previous versions of the db to keep as db.json.1, db.json.2, etc."),
+  ("suppress_tombstoned_items", "Whether or not to leave out items that were deleted from the feed they came from\n(an item counts as deleted when it disappears from its feed while older items are still in it)"),
+  ("max_revisions_per_item", "How many earlier versions of each item to keep when a fetch changes it (see `history <guid>`)"),
+  ("updated_marker", "Added to the end of the title of items whose title or content changed after they were first fetched,\nEX: \"(updated)\" (nothing is added if empty)"),
+  ("dedup_by", "How items posted to more than one feed are recognized as the same post, any of:\n  \"link\" - they link to the same page\n  \"content\" - their text is the same\n  \"marker\" - one says it is a copy of the other (see cross_post_marker)\n[] turns it off"),
+  ("dedup_winner", "Which copy of a duplicate post is output: \"longest_content\", \"feed_priority\" (see the per feed `priority`) or \"earliest\""),
+  ("cross_post_marker", "A regex matching a note in a post saying it is a copy of another post, used by dedup_by = [\"marker\"];\nthe first group (or the whole match) is the link to the original, EX: \"Originally posted at (https://[^\\\\s<]+)\"")
+];
+
+const FEEDS_COMMENT: &str = "Per feed settings:
+  title - overrides the title of the feed (the feed's own title is used if empty)
+  link - overrides the link to the feed (the feed's own link is used if empty)
+  manipulate_input - a command the raw feed is piped into before it is parsed, EX: `sed s/something/something-else/`
+  retention - which fetched items are kept, applied after every fetch (see `prune --dry-run`):
+    `keep_all` keeps everything ever fetched,
+    `keep_last 100` keeps the newest 100 items,
+    `keep_newer_than 30days` keeps items published in the last 30 days,
+    `mirror_upstream` keeps only the items still in the feed itself
+  tags - used to pick out groups of feeds with `fetch --tag <tag>`
+  priority - which feed's copy of a duplicate post is output with `dedup_winner = \"feed_priority\"`, highest first
+  output_name - the name of the feed's own output with `output_one_channel = false`, EX: `art` for `rss-art.xml`
+    (a slug of the feed's title is used if empty)";
+
+impl Config {
+  /// Pulls the settings out of a db, used to create the config file for dbs
+  /// from before the settings were moved out of it
+  pub fn from_db(db: &Db) -> Config {
+    Config {
+      settings: db.settings.clone(),
+      feeds: db.rss.iter().map(|(url, options)| (url.clone(), options.settings.clone())).collect()
+    }
+  }
+  /// Renders the config as TOML with a comment explaining each setting
+  pub fn to_commented_toml(&self) -> Result<String, ConfigError> {
+    let toml = toml::to_string(&self).map_err(ConfigError::FormattingError)?;
+    let mut document = toml.parse::<DocumentMut>().map_err(ConfigError::TomlDocumentError)?;
+    for (key, comment) in SETTING_COMMENTS {
+      if let Some(mut key) = document.key_mut(key) {
+        key.leaf_decor_mut().set_prefix(format!("\n{}", comment_lines(comment)));
+      }
+    }
+    if let Some(feeds) = document.get_mut("feeds").and_then(|feeds| feeds.as_table_mut()) {
+      // `[feeds]` itself is only written out when there aren't any feeds in it yet,
+      // otherwise the comment goes above the first feed
+      let first_feed = feeds.iter_mut().next().and_then(|(_, feed)| feed.as_table_mut());
+      let decor = match first_feed {
+        Some(first_feed) => first_feed.decor_mut(),
+        None => feeds.decor_mut()
+      };
+      decor.set_prefix(format!("\n{}", comment_lines(FEEDS_COMMENT)));
+    }
+    Ok(format!("{}{}", HEADER, document))
+  }
+}
+
+fn comment_lines(comment: &str) -> String {
+  comment.lines().map(|line| format!("# {}\n", line)).collect::<String>()
+}
+
+fn write_file(path: &str, contents: &str) -> Result<(), ConfigError> {
+  // write next to the config and swap it in so a crash can't leave half a config behind
+  let temp_path = format!("{path}.tmp");
+  std::fs::write(&temp_path, contents).map_err(ConfigError::FileWriteError)?;
+  std::fs::rename(&temp_path, path).map_err(ConfigError::FileWriteError)
+}
+
+// renames options from older versions in place, keeping the comments around them
+fn upgrade_document(document: &mut DocumentMut) -> bool {
+  let mut changed = false;
+  let feeds = document.get_mut("feeds").and_then(|feeds| feeds.as_table_like_mut());
+  for (_, feed) in feeds.into_iter().flat_map(|feeds| feeds.iter_mut()) {
+    let Some(feed) = feed.as_table_like_mut() else {
+      continue;
+    };
+    let key_decor = feed.key_mut("retain_all_entries").map(|key| key.leaf_decor().clone());
+    if let (Some(key_decor), Some(retain_all_entries)) = (key_decor, feed.remove("retain_all_entries")) {
+      let retention = migrations::retention_from_retain_all_entries(retain_all_entries.as_bool());
+      let mut value = toml_edit::Value::from(retention);
+      if let Some(decor) = retain_all_entries.as_value().map(|value| value.decor()) {
+        *value.decor_mut() = decor.clone();
+      }
+      feed.insert("retention", toml_edit::Item::Value(value));
+      if let Some(mut new_key) = feed.key_mut("retention") {
+        *new_key.leaf_decor_mut() = key_decor;
+      }
+      changed = true;
+    }
+  }
+  changed
+}
+
+/// The config file as it is on disk; edits made through it keep any comments
+/// and formatting the file already has
+pub struct ConfigFile {
+  pub path: String,
+  pub config: Config,
+  document: DocumentMut
+}
+
+impl ConfigFile {
+  /// Loads the config file and applies it to the db. If there isn't one yet
+  /// it is created from the settings stored in the db.
+  pub fn load_or_migrate(path: &str, db: &mut Db) -> Result<ConfigFile, ConfigError> {
+    let text = if Path::new(path).exists() {
+      let text = std::fs::read_to_string(path).map_err(ConfigError::FileReadError)?;
+      let mut document = text.parse::<DocumentMut>().map_err(ConfigError::TomlDocumentError)?;
+      if upgrade_document(&mut document) {
+        let text = document.to_string();
+        write_file(path, &text)?;
+        log::info!("✅ Upgraded {path} to the current set of options");
+        text
+      } else {
+        text
+      }
+    } else {
+      let text = Config::from_db(db).to_commented_toml()?;
+      write_file(path, &text)?;
+      log::info!("✅ Moved the settings in the db into {path}");
+      text
+    };
+    let config = toml::from_str::<Config>(&text).map_err(ConfigError::TomlDeserializeError)?;
+    let document = text.parse::<DocumentMut>().map_err(ConfigError::TomlDocumentError)?;
+    let config_file = ConfigFile { path: path.to_string(), config, document };
+    config_file.apply(db);
+    Ok(config_file)
+  }
+  /// Puts the settings in the config file into the db
+  pub fn apply(&self, db: &mut Db) {
+    db.settings = self.config.settings.clone();
+    // the config decides which feeds are subscribed, but a feed taken out of it by hand (or
+    // whose url got mistyped) keeps its items until it is put back or `remove`d
+    let mut missing = db.rss.iter_mut()
+      .filter(|(url, _)| !self.config.feeds.contains_key(*url))
+      .collect::<Vec<_>>();
+    missing.sort_by_key(|(url, _)| *url);
+    for (url, options) in missing {
+      log::warn!("⚠️ {url} isn't in {}, so it isn't fetched or output; its items are kept until it's added back or removed with `remove`", self.path);
+      options.missing_from_config = true;
+    }
+    for (url, settings) in self.config.feeds.iter() {
+      let options = db.rss.entry(url.clone()).or_default();
+      options.settings = settings.clone();
+      options.forget_stale_validators();
+    }
+  }
+  /// Writes any feeds that were added, removed or changed in the db back to the config file
+  pub fn sync_feeds(&mut self, db: &Db) -> Result<(), ConfigError> {
+    let mut changed = false;
+    let removed = self.config.feeds.keys()
+      .filter(|url| !db.rss.contains_key(*url))
+      .cloned()
+      .collect::<Vec<_>>();
+    for url in removed {
+      if let Some(feeds) = self.document.get_mut("feeds").and_then(|feeds| feeds.as_table_mut()) {
+        feeds.remove(&url);
+      }
+      self.config.feeds.remove(&url);
+      changed = true;
+    }
+    // feeds missing from the config stay out of it
+    let mut urls = db.rss.iter()
+      .filter(|(_, options)| !options.missing_from_config)
+      .map(|(url, _)| url)
+      .collect::<Vec<_>>();
+    urls.sort();
+    for url in urls {
+      let settings = &db.rss[url].settings;
+      let previous = self.config.feeds.get(url).cloned();
+      if previous.as_ref() == Some(settings) {
+        continue;
+      }
+      let previous = toml::Table::try_from(previous.unwrap_or_default()).map_err(ConfigError::FormattingError)?;
+      let current = toml::Table::try_from(settings).map_err(ConfigError::FormattingError)?;
+      let current_items = toml_edit::ser::to_document(settings).map_err(ConfigError::DocumentFormattingError)?;
+      let feeds = self.document
+        .entry("feeds")
+        .or_insert_with(|| {
+          let mut feeds = toml_edit::Table::new();
+          feeds.set_implicit(true);
+          toml_edit::Item::Table(feeds)
+        });
+      let is_new = feeds.get(url.as_str()).is_none();
+      if is_new {
+        feeds[url.as_str()] = toml_edit::table();
+      }
+      let feed = &mut feeds[url.as_str()];
+      // only touch the values that changed so any comments next to the others stay put, and
+      // settings left out of an existing table keep falling back on their defaults
+      for (key, item) in current_items.iter() {
+        if is_new || previous.get(key) != current.get(key) {
+          feed[key] = item.clone();
+        }
+      }
+      for key in previous.keys().filter(|key| !current.contains_key(*key)) {
+        if let Some(feed) = feed.as_table_like_mut() {
+          feed.remove(key);
+        }
+      }
+      self.config.feeds.insert(url.clone(), settings.clone());
+      changed = true;
+    }
+    if changed {
+      write_file(&self.path, &self.document.to_string())?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helpers::*;
+
+  const URL: &str = "https://feed.example/rss.xml";
+
+  fn config_path(name: &str) -> String {
+    temp_dir(name).join("config.toml").to_string_lossy().to_string()
+  }
+
+  fn db_with_feed(url: &str) -> Db {
+    let mut db = Db::new();
+    db.rss.insert(url.to_string(), FeedOptions::default());
+    db
+  }
+
+  #[test]
+  fn moves_the_settings_out_of_the_db() {
+    let path = config_path("config-from-db");
+    let mut db = db_with_feed(URL);
+    db.settings.title = String::from("Emma");
+    db.settings.max_entries_published = 5;
+    db.rss.get_mut(URL).unwrap().settings.manipulate_input = String::from("cat");
+    let config_file = ConfigFile::load_or_migrate(&path, &mut db).unwrap();
+    assert_eq!(config_file.config, Config::from_db(&db));
+    // and they come back from the file into a db without them
+    let mut loaded = db_with_feed(URL);
+    ConfigFile::load_or_migrate(&path, &mut loaded).unwrap();
+    assert_eq!(loaded, db);
+  }
+
+  #[test]
+  fn keeps_feeds_missing_from_the_config() {
+    let path = config_path("config-missing-feed");
+    std::fs::write(&path, "[feeds.\"https://feed.example/rss.xml \"]\n").unwrap();
+    let mut db = db_with_feed(URL);
+    db.rss.get_mut(URL).unwrap().rss.channel.item.push(item("a", None));
+    let mut config_file = ConfigFile::load_or_migrate(&path, &mut db).unwrap();
+    let options = &db.rss[URL];
+    assert!(options.missing_from_config);
+    assert_eq!(guids(&options.rss.channel.item), vec!["a"]);
+    // and it isn't written back into the config
+    config_file.sync_feeds(&db).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "[feeds.\"https://feed.example/rss.xml \"]\n");
+  }
+
+  #[test]
+  fn syncs_only_what_changed() {
+    let path = config_path("config-sync");
+    let text = "title = \"Emma\"\n\n[feeds.\"https://feed.example/rss.xml\"]\n# runs before parsing\nmanipulate_input = \"cat\"\n\n[feeds.\"https://other.example/feed.json\"]\n";
+    std::fs::write(&path, text).unwrap();
+    let mut db = db_with_feed(URL);
+    let mut config_file = ConfigFile::load_or_migrate(&path, &mut db).unwrap();
+    assert_eq!(db.settings.title, "Emma");
+    assert_eq!(db.rss[URL].settings.manipulate_input, "cat");
+    // nothing changed, so nothing is written (or back-filled with defaults)
+    config_file.sync_feeds(&db).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+    db.rss.get_mut(URL).unwrap().settings.tags = vec![String::from("art")];
+    db.rss.remove("https://other.example/feed.json");
+    db.rss.insert(String::from("https://new.example/atom.xml"), FeedOptions::default());
+    config_file.sync_feeds(&db).unwrap();
+    let synced = std::fs::read_to_string(&path).unwrap();
+    assert!(synced.starts_with("title = \"Emma\"\n\n[feeds.\"https://feed.example/rss.xml\"]\n# runs before parsing\nmanipulate_input = \"cat\"\ntags = [\"art\"]\n"), "{synced}");
+    assert!(!synced.contains("other.example"), "{synced}");
+    // a new feed gets every setting written out
+    let new_feed = synced.split("[feeds.\"https://new.example/atom.xml\"]").nth(1).unwrap();
+    assert!(new_feed.contains("retention = \"keep_all\""), "{synced}");
+    let mut reloaded = Db::new();
+    ConfigFile::load_or_migrate(&path, &mut reloaded).unwrap();
+    assert_eq!(reloaded.rss.keys().collect::<std::collections::BTreeSet<_>>(), db.rss.keys().collect());
+    assert_eq!(reloaded.rss[URL].settings, db.rss[URL].settings);
+  }
+
+  #[test]
+  fn upgrades_retain_all_entries_in_place() {
+    let path = config_path("config-upgrade");
+    std::fs::write(&path, "[feeds.\"https://feed.example/rss.xml\"]\n# keep it all\nretain_all_entries = true\n").unwrap();
+    let mut db = Db::new();
+    ConfigFile::load_or_migrate(&path, &mut db).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "[feeds.\"https://feed.example/rss.xml\"]\n# keep it all\nretention = \"keep_all\"\n");
+  }
+}
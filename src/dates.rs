@@ -0,0 +1,206 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+// timezone names feeds use in place of offsets
+const TIMEZONE_NAMES: &[(&str, &str)] = &[
+  ("UTC", "+0000"),
+  ("GMT", "+0000"),
+  ("UT", "+0000"),
+  ("Z", "+0000"),
+  ("EST", "-0500"),
+  ("EDT", "-0400"),
+  ("CST", "-0600"),
+  ("CDT", "-0500"),
+  ("MST", "-0700"),
+  ("MDT", "-0600"),
+  ("PST", "-0800"),
+  ("PDT", "-0700"),
+  ("AKST", "-0900"),
+  ("AKDT", "-0800"),
+  ("HST", "-1000")
+];
+
+// formats with an offset, tried after RFC 2822 and RFC 3339
+const FORMATS_WITH_OFFSET: &[&str] = &[
+  "%d %b %Y %H:%M:%S %z",
+  "%d %b %Y %H:%M %z",
+  "%d %B %Y %H:%M:%S %z",
+  "%Y-%m-%dT%H:%M:%S%.f%z",
+  "%Y-%m-%dT%H:%M%z",
+  "%Y-%m-%d %H:%M:%S%.f %z",
+  "%Y-%m-%d %H:%M:%S%.f%z"
+];
+
+// formats without an offset, which are taken to be UTC
+const FORMATS_WITHOUT_OFFSET: &[&str] = &[
+  "%d %b %Y %H:%M:%S",
+  "%d %b %Y %H:%M",
+  "%Y-%m-%dT%H:%M:%S%.f",
+  "%Y-%m-%dT%H:%M",
+  "%Y-%m-%d %H:%M:%S%.f",
+  "%Y-%m-%d %H:%M"
+];
+
+// the weekday is redundant and often wrong (or in another language), so it's dropped
+fn strip_weekday(date: &str) -> &str {
+  match date.split_once(',') {
+    Some((weekday, rest)) if weekday.chars().all(|c| c.is_alphabetic()) => rest.trim(),
+    _ => date
+  }
+}
+
+// swaps a timezone name at the end of the date for its offset
+fn replace_timezone_name(date: &str) -> String {
+  if let Some((rest, name)) = date.rsplit_once(' ') {
+    for (timezone_name, offset) in TIMEZONE_NAMES {
+      if name.eq_ignore_ascii_case(timezone_name) {
+        return format!("{} {}", rest, offset);
+      }
+    }
+  }
+  // RFC 3339 style, EX: 2023-08-16T18:00:00Z
+  match date.strip_suffix('Z').or(date.strip_suffix('z')) {
+    Some(rest) if rest.contains('T') => format!("{}+0000", rest),
+    _ => date.to_string()
+  }
+}
+
+// offsets like `+01:00` at the end of an RFC 2822 style date
+fn remove_offset_colon(date: &str) -> String {
+  let bytes = date.as_bytes();
+  let length = bytes.len();
+  if length > 6 && (bytes[length - 6] == b'+' || bytes[length - 6] == b'-') && bytes[length - 3] == b':' {
+    format!("{}{}", &date[..length - 3], &date[length - 2..])
+  } else {
+    date.to_string()
+  }
+}
+
+/// Reads a date in any of the formats feeds use in practice: RFC 2822 (with or without
+/// the weekday, seconds or a numeric offset, and with named US timezones), RFC 3339 (with
+/// or without fractional seconds) and the common ways both are mangled
+pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+  let date = date.trim();
+  if date.is_empty() {
+    return None;
+  }
+  if let Ok(parsed) = DateTime::parse_from_rfc2822(date) {
+    return Some(parsed);
+  }
+  if let Ok(parsed) = DateTime::parse_from_rfc3339(date) {
+    return Some(parsed);
+  }
+  let cleaned = remove_offset_colon(&replace_timezone_name(strip_weekday(date)));
+  // the day is padded so single digit days parse everywhere
+  let cleaned = match cleaned.split_once(' ') {
+    Some((day, rest)) if day.len() == 1 && day.chars().all(|c| c.is_ascii_digit()) => format!("0{} {}", day, rest),
+    _ => cleaned
+  };
+  for format in FORMATS_WITH_OFFSET {
+    if let Ok(parsed) = DateTime::parse_from_str(&cleaned, format) {
+      return Some(parsed);
+    }
+  }
+  for format in FORMATS_WITHOUT_OFFSET {
+    if let Ok(parsed) = NaiveDateTime::parse_from_str(&cleaned, format) {
+      return Some(Utc.from_utc_datetime(&parsed).fixed_offset());
+    }
+  }
+  if let Ok(parsed) = NaiveDate::parse_from_str(&cleaned, "%Y-%m-%d") {
+    return parsed.and_hms_opt(0, 0, 0).map(|parsed| Utc.from_utc_datetime(&parsed).fixed_offset());
+  }
+  None
+}
+
+/// The unix timestamp of a date, see `parse_date`
+pub fn parse_timestamp(date: &str) -> Option<i64> {
+  parse_date(date).map(|parsed| parsed.timestamp())
+}
+
+/// Formats a unix timestamp the way RSS expects dates
+pub fn format_rfc2822(timestamp: i64) -> Option<String> {
+  Utc.timestamp_opt(timestamp, 0).single().map(|datetime| datetime.to_rfc2822())
+}
+
+/// Formats a unix timestamp the way Atom and JSON Feed expect dates
+pub fn format_rfc3339(timestamp: i64) -> Option<String> {
+  Utc.timestamp_opt(timestamp, 0).single().map(|datetime| datetime.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 2023-08-16T18:00:00Z
+  const TIMESTAMP: i64 = 1692208800;
+
+  #[test]
+  fn parses_rfc2822() {
+    assert_eq!(parse_timestamp("Wed, 16 Aug 2023 18:00:00 +0000"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("Wed, 16 Aug 2023 18:00:00 GMT"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("  Wed, 16 Aug 2023 20:00:00 +0200  "), Some(TIMESTAMP));
+  }
+
+  #[test]
+  fn parses_named_timezones() {
+    assert_eq!(parse_timestamp("Wed, 16 Aug 2023 14:00:00 EDT"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("Wed, 16 Aug 2023 10:00:00 PST"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("16 Aug 2023 08:00:00 HST"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("16 Aug 2023 18:00:00 utc"), Some(TIMESTAMP));
+  }
+
+  #[test]
+  fn parses_single_digit_days() {
+    assert_eq!(parse_timestamp("Sun, 6 Aug 2023 18:00:00 +0000"), Some(1691344800));
+    assert_eq!(parse_timestamp("6 Aug 2023 18:00 +0000"), Some(1691344800));
+  }
+
+  #[test]
+  fn parses_offsets_with_a_colon() {
+    assert_eq!(parse_timestamp("Wed, 16 Aug 2023 20:00:00 +02:00"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("16 Aug 2023 13:00:00 -05:00"), Some(TIMESTAMP));
+  }
+
+  #[test]
+  fn ignores_wrong_weekdays() {
+    // 16 August 2023 was a Wednesday
+    assert_eq!(parse_timestamp("Mon, 16 Aug 2023 18:00:00 +0000"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("Mittwoch, 16 Aug 2023 18:00:00 +0000"), Some(TIMESTAMP));
+  }
+
+  #[test]
+  fn parses_rfc3339() {
+    assert_eq!(parse_timestamp("2023-08-16T18:00:00Z"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("2023-08-16T18:00:00.123Z"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("2023-08-17T03:00:00+09:00"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("2023-08-16T18:00Z"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("2023-08-16 18:00:00 +0000"), Some(TIMESTAMP));
+  }
+
+  #[test]
+  fn takes_dates_without_an_offset_as_utc() {
+    assert_eq!(parse_timestamp("2023-08-16T18:00:00"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("2023-08-16 18:00"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("16 Aug 2023 18:00:00"), Some(TIMESTAMP));
+    assert_eq!(parse_timestamp("2023-08-16"), Some(1692144000));
+  }
+
+  #[test]
+  fn keeps_the_offset() {
+    let parsed = parse_date("Wed, 16 Aug 2023 20:00:00 +02:00").unwrap();
+    assert_eq!(parsed.offset().local_minus_utc(), 2 * 60 * 60);
+  }
+
+  #[test]
+  fn rejects_what_isnt_a_date() {
+    assert_eq!(parse_date(""), None);
+    assert_eq!(parse_date("   "), None);
+    assert_eq!(parse_date("yesterday"), None);
+    assert_eq!(parse_date("Wed, 32 Aug 2023 18:00:00 +0000"), None);
+  }
+
+  #[test]
+  fn formats_timestamps() {
+    assert_eq!(format_rfc2822(TIMESTAMP).as_deref(), Some("Wed, 16 Aug 2023 18:00:00 +0000"));
+    assert_eq!(format_rfc3339(TIMESTAMP).as_deref(), Some("2023-08-16T18:00:00Z"));
+  }
+}
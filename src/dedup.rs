@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::structs::*;
+
+/// A way of telling that two items from different feeds are the same post
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupStrategy {
+  // the links point to the same page once tracking parameters and the like are stripped
+  Link,
+  // the text of the posts is the same once the markup is stripped
+  Content,
+  // one post says it is a copy of the other (see `Settings::cross_post_marker`)
+  Marker
+}
+
+/// Which of a group of duplicates ends up in the output
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupWinner {
+  // the one with the most text
+  #[default]
+  LongestContent,
+  // the one from the feed with the highest `priority`
+  FeedPriority,
+  // the one published first
+  Earliest
+}
+
+/// Drops the scheme, `www.`, fragments, tracking parameters and trailing slashes (and
+/// lowercases the host), so the same page linked from different places compares equal
+pub fn normalize_link(link: &str) -> String {
+  let mut url = match reqwest::Url::parse(link.trim()) {
+    Ok(url) => url,
+    Err(_) => return link.trim().trim_end_matches('/').to_lowercase()
+  };
+  url.set_fragment(None);
+  let query = url.query_pairs()
+    .filter(|(key, _)| !key.starts_with("utm_") && key != "ref" && key != "fbclid")
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect::<Vec<_>>();
+  if query.is_empty() {
+    url.set_query(None);
+  } else {
+    url.query_pairs_mut().clear().extend_pairs(query);
+  }
+  let host = url.host_str().unwrap_or("").trim_start_matches("www.").to_string();
+  format!("{}{}{}", host, url.path().trim_end_matches('/'), url.query().map(|query| format!("?{query}")).unwrap_or_default())
+}
+
+fn get_content(item: &Item) -> &str {
+  item.content_encoded.as_deref().or(item.description.as_deref()).unwrap_or("")
+}
+
+fn get_text(item: &Item, tag_regex: &Regex) -> String {
+  tag_regex.replace_all(get_content(item), " ").split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+fn get_key(strategy: DedupStrategy, item: &Item, tag_regex: &Regex, marker_regex: Option<&Regex>) -> Option<String> {
+  match strategy {
+    DedupStrategy::Link => item.link.as_deref().map(normalize_link),
+    DedupStrategy::Content => {
+      let text = get_text(item, tag_regex);
+      if text.is_empty() {
+        return None;
+      }
+      let mut hasher = std::collections::hash_map::DefaultHasher::new();
+      text.hash(&mut hasher);
+      Some(format!("{:x}", hasher.finish()))
+    },
+    DedupStrategy::Marker => {
+      // a marked post is keyed by the link it points to and any other post by its own
+      // link, so the copy ends up with the original
+      let marked_link = marker_regex.and_then(|marker_regex| {
+        marker_regex.captures(get_content(item))
+          .and_then(|captures| captures.get(1).or(captures.get(0)))
+          .map(|link| link.as_str().to_string())
+      });
+      marked_link.or(item.link.clone()).map(|link| normalize_link(&link))
+    }
+  }
+}
+
+fn find(groups: &mut [usize], position: usize) -> usize {
+  let mut root = position;
+  while groups[root] != root {
+    root = groups[root];
+  }
+  groups[position] = root;
+  root
+}
+
+/// Collapses items from different feeds that are the same post (by any of the given
+/// strategies) into one, keeping the item the winner rule picks. Each item comes with
+/// the URL and priority of its feed.
+pub fn dedup_items(items: Vec<(Item, String, i32)>, strategies: &[DedupStrategy], winner: DedupWinner, cross_post_marker: &str) -> Vec<Item> {
+  if strategies.is_empty() {
+    return items.into_iter().map(|(item, _, _)| item).collect();
+  }
+  let tag_regex = Regex::new(r#"<[^>]*>"#).unwrap();
+  let marker_regex = if cross_post_marker.is_empty() {
+    None
+  } else {
+    match Regex::new(cross_post_marker) {
+      Ok(marker_regex) => Some(marker_regex),
+      Err(error) => {
+        log::error!("❌ Invalid cross_post_marker: {}", error);
+        None
+      }
+    }
+  };
+  // every item starts out in a group of its own and groups are joined whenever two items
+  // share a key; separate posts in one feed can share a link, so those are never joined
+  let mut groups = (0..items.len()).collect::<Vec<_>>();
+  for strategy in strategies {
+    let mut first_with_key = HashMap::<String, usize>::new();
+    for (position, (item, feed_url, _)) in items.iter().enumerate() {
+      if let Some(key) = get_key(*strategy, item, &tag_regex, marker_regex.as_ref()) {
+        match first_with_key.get(&key) {
+          Some(first) if items[*first].1 == *feed_url => {},
+          Some(first) => {
+            let (root, first_root) = (find(&mut groups, position), find(&mut groups, *first));
+            groups[root] = first_root;
+          },
+          None => {
+            first_with_key.insert(key, position);
+          }
+        }
+      }
+    }
+  }
+  let mut winners = HashMap::<usize, usize>::new();
+  for position in 0..items.len() {
+    let root = find(&mut groups, position);
+    let current = winners.entry(root).or_insert(position);
+    if beats(&items[position], &items[*current], winner, &tag_regex) {
+      *current = position;
+    }
+  }
+  let kept = winners.values().copied().collect::<std::collections::HashSet<_>>();
+  let duplicates = items.len() - kept.len();
+  if duplicates > 0 {
+    log::info!("✅ Left out {} duplicate item(s)", duplicates);
+  }
+  items.into_iter()
+    .enumerate()
+    .filter(|(position, _)| kept.contains(position))
+    .map(|(_, (item, _, _))| item)
+    .collect()
+}
+
+// whether `challenger` should replace `current` as the item kept from a group
+fn beats(challenger: &(Item, String, i32), current: &(Item, String, i32), winner: DedupWinner, tag_regex: &Regex) -> bool {
+  let content_length = |item: &Item| get_text(item, tag_regex).len();
+  let (challenger_item, _, challenger_priority) = challenger;
+  let (current_item, _, current_priority) = current;
+  match winner {
+    DedupWinner::LongestContent => content_length(challenger_item) > content_length(current_item),
+    DedupWinner::FeedPriority => {
+      challenger_priority > current_priority
+        || (challenger_priority == current_priority && content_length(challenger_item) > content_length(current_item))
+    },
+    DedupWinner::Earliest => {
+      // an item with a date wins over one without
+      match (challenger_item.get_published_timestamp(), current_item.get_published_timestamp()) {
+        (Some(challenger_time), Some(current_time)) => challenger_time < current_time,
+        (Some(_), None) => true,
+        _ => false
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use crate::test_helpers::*;
+
+  fn post(guid: &str, link: &str, description: &str) -> Item {
+    item_with(json!({ "guid": guid, "link": link, "description": description }))
+  }
+
+  fn from(feed_url: &str, item: Item) -> (Item, String, i32) {
+    (item, feed_url.to_string(), 0)
+  }
+
+  #[test]
+  fn normalizes_links() {
+    assert_eq!(normalize_link("https://www.Example.com/post/1/"), "example.com/post/1");
+    assert_eq!(normalize_link("http://example.com/post/1#comments"), "example.com/post/1");
+    assert_eq!(normalize_link("https://example.com/post/1?utm_source=rss&ref=feed&fbclid=x"), "example.com/post/1");
+    assert_eq!(normalize_link("https://example.com/post?id=1&utm_medium=social"), "example.com/post?id=1");
+    assert_eq!(normalize_link(" not a url/ "), "not a url");
+  }
+
+  #[test]
+  fn leaves_items_alone_without_strategies() {
+    let items = vec![from("a", post("1", "https://x/1", "")), from("b", post("2", "https://x/1", ""))];
+    assert_eq!(guids(&dedup_items(items, &[], DedupWinner::LongestContent, "")), vec!["1", "2"]);
+  }
+
+  #[test]
+  fn joins_items_from_different_feeds_by_link() {
+    let items = vec![
+      from("a", post("1", "https://www.x/1?utm_source=a", "short")),
+      from("b", post("2", "https://x/1", "a bit longer")),
+      from("b", post("3", "https://x/2", "other"))
+    ];
+    assert_eq!(guids(&dedup_items(items, &[DedupStrategy::Link], DedupWinner::LongestContent, "")), vec!["2", "3"]);
+  }
+
+  #[test]
+  fn never_joins_items_from_the_same_feed() {
+    // two posts in one feed linking the same article are still two posts
+    let items = vec![
+      from("a", post("1", "https://x/article", "first take")),
+      from("a", post("2", "https://x/article", "second take"))
+    ];
+    assert_eq!(guids(&dedup_items(items, &[DedupStrategy::Link], DedupWinner::LongestContent, "")), vec!["1", "2"]);
+  }
+
+  #[test]
+  fn joins_items_by_content() {
+    let items = vec![
+      from("a", post("1", "https://a/1", "<p>Hello   World</p>")),
+      from("b", post("2", "https://b/1", "hello world")),
+      from("c", post("3", "https://c/1", "something else"))
+    ];
+    assert_eq!(dedup_items(items, &[DedupStrategy::Content], DedupWinner::LongestContent, "").len(), 2);
+  }
+
+  #[test]
+  fn joins_items_by_cross_post_marker() {
+    let items = vec![
+      from("blog", post("original", "https://blog/post", "the post")),
+      from("social", post("copy", "https://social/1", "the post, originally posted at https://blog/post"))
+    ];
+    let kept = dedup_items(items, &[DedupStrategy::Marker], DedupWinner::Earliest, r"originally posted at (https://\S+)");
+    assert_eq!(kept.len(), 1);
+  }
+
+  #[test]
+  fn groups_are_joined_across_strategies() {
+    // 1 and 2 share a link and 2 and 3 share their text, so all three are one post
+    let items = vec![
+      from("a", post("1", "https://x/1", "one")),
+      from("b", post("2", "https://x/1", "same text")),
+      from("c", post("3", "https://c/1", "same text")),
+      from("d", post("4", "https://d/1", "unrelated"))
+    ];
+    let kept = dedup_items(items, &[DedupStrategy::Link, DedupStrategy::Content], DedupWinner::LongestContent, "");
+    assert_eq!(guids(&kept), vec!["2", "4"]);
+  }
+
+  #[test]
+  fn same_feed_items_only_stay_apart_from_each_other() {
+    // 4 shares 2's link but also its feed, so it stays on its own while 5 joins 2
+    let items = vec![
+      from("a", post("1", "https://x/1", "aaaa")),
+      from("b", post("2", "https://x/2", "bbbb")),
+      from("c", post("3", "https://x/1", "cc")),
+      from("b", post("4", "https://x/2", "dd")),
+      from("e", post("5", "https://x/2", "the longest of them all"))
+    ];
+    let kept = dedup_items(items, &[DedupStrategy::Link], DedupWinner::LongestContent, "");
+    assert_eq!(guids(&kept), vec!["1", "4", "5"]);
+  }
+
+  #[test]
+  fn picks_the_winner() {
+    let early = item_with(json!({ "guid": "early", "link": "https://x/1", "description": "short", "pubDate": "Tue, 15 Aug 2023 18:00:00 +0000" }));
+    let late = item_with(json!({ "guid": "late", "link": "https://x/1", "description": "much longer text", "pubDate": "Wed, 16 Aug 2023 18:00:00 +0000" }));
+    let items = || vec![(early.clone(), String::from("a"), 1), (late.clone(), String::from("b"), 0)];
+    assert_eq!(guids(&dedup_items(items(), &[DedupStrategy::Link], DedupWinner::LongestContent, "")), vec!["late"]);
+    assert_eq!(guids(&dedup_items(items(), &[DedupStrategy::Link], DedupWinner::FeedPriority, "")), vec!["early"]);
+    assert_eq!(guids(&dedup_items(items(), &[DedupStrategy::Link], DedupWinner::Earliest, "")), vec!["early"]);
+  }
+
+  #[test]
+  fn keeps_the_order_of_the_items() {
+    let items = vec![
+      from("a", post("3", "https://x/3", "")),
+      from("a", post("1", "https://x/1", "")),
+      from("b", post("2", "https://x/3", ""))
+    ];
+    let kept = dedup_items(items, &[DedupStrategy::Link], DedupWinner::Earliest, "");
+    assert_eq!(guids(&kept), vec!["3", "1"]);
+  }
+}
@@ -0,0 +1,104 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::bytes::Regex;
+
+/// A feed body transcoded to UTF-8
+pub struct DecodedFeed {
+  pub text: String,
+  pub encoding: &'static Encoding,
+  // whether some bytes weren't valid in `encoding` and were replaced
+  pub had_errors: bool
+}
+
+fn encoding_from_content_type(content_type: &str) -> Option<&'static Encoding> {
+  content_type.split(';')
+    .skip(1)
+    .filter_map(|parameter| parameter.split_once('='))
+    .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+    .and_then(|(_, charset)| Encoding::for_label(charset.trim().trim_matches('"').as_bytes()))
+}
+
+// the `encoding` in `<?xml version="1.0" encoding="ISO-8859-1"?>`, which can be read as
+// ASCII whatever the encoding is (short of UTF-16, which needs a BOM)
+fn encoding_from_xml_declaration(body: &[u8]) -> Option<&'static Encoding> {
+  let start = &body[..body.len().min(1024)];
+  if !start.starts_with(b"<?xml") {
+    return None;
+  }
+  let declaration = &start[..start.windows(2).position(|window| window == b"?>")?];
+  let re = Regex::new(r#"encoding\s*=\s*["']([A-Za-z0-9._:-]+)["']"#).unwrap();
+  let label = re.captures(declaration)?.get(1)?.as_bytes();
+  match Encoding::for_label(label) {
+    // the declaration was readable as ASCII, so it can't really be UTF-16
+    Some(encoding) if encoding == UTF_16LE || encoding == UTF_16BE => Some(UTF_8),
+    encoding => encoding
+  }
+}
+
+/// Works out the character encoding of a feed from (in order of precedence) its byte
+/// order mark, the charset in the Content-Type it was sent with and its XML
+/// declaration, falling back on UTF-8
+pub fn detect_encoding(body: &[u8], content_type: Option<&str>) -> &'static Encoding {
+  if let Some((encoding, _)) = Encoding::for_bom(body) {
+    return encoding;
+  }
+  content_type.and_then(encoding_from_content_type)
+    .or_else(|| encoding_from_xml_declaration(body))
+    .unwrap_or(UTF_8)
+}
+
+/// Transcodes a feed body to UTF-8 (see `detect_encoding`), dropping the byte order mark
+pub fn decode(body: &[u8], content_type: Option<&str>) -> DecodedFeed {
+  let (text, encoding, had_errors) = detect_encoding(body, content_type).decode(body);
+  DecodedFeed { text: text.into_owned(), encoding, had_errors }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use encoding_rs::{ISO_8859_2, SHIFT_JIS, WINDOWS_1252};
+
+  const LATIN_1_FEED: &[u8] = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><rss><channel><title>Caf\xe9</title></channel></rss>";
+
+  #[test]
+  fn reads_the_xml_declaration() {
+    // ISO-8859-1 is read as windows-1252, like browsers do
+    assert_eq!(detect_encoding(LATIN_1_FEED, None), WINDOWS_1252);
+    assert_eq!(detect_encoding(b"<?xml version='1.0' encoding = 'shift_jis' ?><rss/>", Some("application/xml")), SHIFT_JIS);
+  }
+
+  #[test]
+  fn prefers_the_content_type_over_the_declaration() {
+    assert_eq!(detect_encoding(LATIN_1_FEED, Some("application/rss+xml; charset=\"iso-8859-2\"")), ISO_8859_2);
+    // an unknown charset is no help
+    assert_eq!(detect_encoding(LATIN_1_FEED, Some("text/xml; charset=nonsense")), WINDOWS_1252);
+  }
+
+  #[test]
+  fn prefers_the_byte_order_mark_over_everything() {
+    let mut body = vec![0xff, 0xfe];
+    body.extend("<rss/>".encode_utf16().flat_map(u16::to_le_bytes));
+    assert_eq!(detect_encoding(&body, Some("text/xml; charset=windows-1252")), UTF_16LE);
+    assert_eq!(decode(&body, None).text, "<rss/>");
+  }
+
+  #[test]
+  fn ignores_a_utf_16_declaration_without_a_byte_order_mark() {
+    assert_eq!(detect_encoding(b"<?xml version=\"1.0\" encoding=\"UTF-16\"?><rss/>", None), UTF_8);
+  }
+
+  #[test]
+  fn falls_back_on_utf_8() {
+    assert_eq!(detect_encoding("<rss><title>Café</title></rss>".as_bytes(), None), UTF_8);
+    assert_eq!(detect_encoding(b"<rss encoding=\"latin1\"/>", None), UTF_8);
+    assert_eq!(detect_encoding(b"", None), UTF_8);
+  }
+
+  #[test]
+  fn transcodes_to_utf_8() {
+    let decoded = decode(LATIN_1_FEED, None);
+    assert!(decoded.text.contains("<title>Café</title>"));
+    assert!(!decoded.had_errors);
+    let decoded = decode(b"<title>Caf\xe9</title>", Some("text/xml; charset=utf-8"));
+    assert!(decoded.had_errors);
+  }
+}
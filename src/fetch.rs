@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+use crate::encoding;
+use crate::formats::{self, ParseError};
+use crate::structs::*;
+
+#[derive(Debug)]
+pub enum FeedError {
+  Reqwest(reqwest::Error),
+  NonSuccessfulStatusCode(reqwest::StatusCode),
+  ManipulateInput(std::io::Error),
+  Parse(ParseError),
+  TaskFailed(tokio::task::JoinError)
+}
+
+impl std::fmt::Display for FeedError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        FeedError::NonSuccessfulStatusCode(code) => write!(f, "Request returned non-successful status code: {}", code),
+        FeedError::Reqwest(error) => write!(f, "Error making request: {}", error),
+        FeedError::ManipulateInput(error) => write!(f, "Error running modification shell script: {}", error),
+        FeedError::Parse(error) => write!(f, "{}", error),
+        FeedError::TaskFailed(error) => write!(f, "Fetch task failed: {}", error)
+     }
+  }
+}
+
+pub enum FetchedFeed {
+  // the body of the feed (as sent, in whatever encoding) along with the
+  // validators to send on the next fetch
+  Modified {
+    body: Vec<u8>,
+    content_type: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>
+  },
+  // the server answered 304, so the copy in the db is still current
+  NotModified
+}
+
+fn get_header(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+  response.headers()
+    .get(name)
+    .and_then(|value| value.to_str().ok())
+    .map(|value| value.to_string())
+}
+
+pub async fn fetch_feed(client: &reqwest::Client, url: &str, etag: Option<&str>, last_modified: Option<&str>) -> Result<FetchedFeed, FeedError> {
+  let mut request = client.get(url);
+  if let Some(etag) = etag {
+    request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+  }
+  if let Some(last_modified) = last_modified {
+    request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+  }
+  let response = match request.send().await {
+    Ok(result) => result,
+    Err(err) => return Err(FeedError::Reqwest(err))
+  };
+  match response.status() {
+    reqwest::StatusCode::OK => {
+      let content_type = get_header(&response, reqwest::header::CONTENT_TYPE);
+      let etag = get_header(&response, reqwest::header::ETAG);
+      let last_modified = get_header(&response, reqwest::header::LAST_MODIFIED);
+      let body = response.bytes().await.map_err(FeedError::Reqwest)?.to_vec();
+      Ok(FetchedFeed::Modified { body, content_type, etag, last_modified })
+    },
+    reqwest::StatusCode::NOT_MODIFIED => {
+      Ok(FetchedFeed::NotModified)
+    },
+    _ => {
+      Err(FeedError::NonSuccessfulStatusCode(response.status()))
+    }
+  }
+}
+
+/// Pipes the raw feed through the user supplied `manipulate_input` command
+/// and returns whatever the command wrote to stdout
+pub async fn manipulate_input(manipulate_input: &str, feed_str: String) -> Result<String, FeedError> {
+  let cmd = manipulate_input.split(' ').collect::<Vec<_>>();
+  let mut child = Command::new(cmd[0])
+    .args(&cmd[1..cmd.len()])
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .map_err(FeedError::ManipulateInput)?;
+  let mut stdin = child.stdin.take().unwrap();
+  // write from a separate task so a command that streams its output can't deadlock on a full pipe
+  let writer = tokio::spawn(async move {
+    stdin.write_all(feed_str.as_bytes()).await
+  });
+  let output = child.wait_with_output().await.map_err(FeedError::ManipulateInput)?;
+  match writer.await {
+    Ok(Ok(())) => {},
+    Ok(Err(error)) => {
+      log::error!("Failed to write to modification shell script: {}", error);
+    },
+    Err(error) => {
+      log::error!("Failed to write to modification shell script: {}", error);
+    }
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub enum FetchOutcome {
+  Updated {
+    rss: Box<Rss>,
+    // the name of the character encoding the feed was sent in
+    encoding: String,
+    etag: Option<String>,
+    last_modified: Option<String>
+  },
+  NotModified
+}
+
+/// Everything a fetch task needs to know about a feed, copied out of the `Db`
+/// so the task doesn't need to borrow it
+pub struct FetchRequest {
+  pub url: String,
+  pub manipulate_input: String,
+  pub etag: Option<String>,
+  pub last_modified: Option<String>
+}
+
+impl FetchRequest {
+  pub fn new(url: &str, db: &Db) -> FetchRequest {
+    match db.rss.get(url) {
+      Some(options) => FetchRequest {
+        url: url.to_string(),
+        manipulate_input: options.settings.manipulate_input.clone(),
+        etag: options.etag.clone(),
+        last_modified: options.last_modified.clone()
+      },
+      None => FetchRequest {
+        url: url.to_string(),
+        manipulate_input: String::from(""),
+        etag: None,
+        last_modified: None
+      }
+    }
+  }
+}
+
+async fn fetch_one(client: &reqwest::Client, request: &FetchRequest) -> Result<FetchOutcome, FeedError> {
+  match fetch_feed(client, &request.url, request.etag.as_deref(), request.last_modified.as_deref()).await? {
+    FetchedFeed::NotModified => Ok(FetchOutcome::NotModified),
+    FetchedFeed::Modified { body, content_type, etag, last_modified } => {
+      let decoded = encoding::decode(&body, content_type.as_deref());
+      if decoded.had_errors {
+        log::warn!("⚠️ {}: some of the feed wasn't valid {} and was replaced", request.url, decoded.encoding.name());
+      }
+      let feed_str = if !request.manipulate_input.is_empty() {
+        manipulate_input(&request.manipulate_input, decoded.text).await?
+      } else {
+        decoded.text
+      };
+      let rss = formats::parse_feed(&feed_str, content_type.as_deref()).map_err(FeedError::Parse)?;
+      let encoding = decoded.encoding.name().to_string();
+      Ok(FetchOutcome::Updated { rss: Box::new(rss), encoding, etag, last_modified })
+    }
+  }
+}
+
+fn get_host(url: &str) -> String {
+  reqwest::Url::parse(url)
+    .ok()
+    .and_then(|url| url.host_str().map(|host| host.to_string()))
+    .unwrap_or_default()
+}
+
+// how long connecting to a host may take, unless the whole fetch is allowed less
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fetches every request concurrently, with at most `max_concurrent` fetches in flight
+/// overall and at most `max_per_host` against any single host. A fetch that takes
+/// longer than `timeout` fails, so one unresponsive host can't hold up the rest.
+///
+/// The results are returned in the same order as the requests regardless of the
+/// order the fetches finish in, so merging them afterwards is deterministic.
+pub async fn fetch_all(requests: Vec<FetchRequest>, max_concurrent: usize, max_per_host: usize, timeout: Duration) -> Result<Vec<(String, Result<FetchOutcome, FeedError>)>, FeedError> {
+  let client = reqwest::Client::builder()
+    .connect_timeout(CONNECT_TIMEOUT.min(timeout))
+    .timeout(timeout)
+    .build()
+    .map_err(FeedError::Reqwest)?;
+  let global_limit = Arc::new(Semaphore::new(max_concurrent.max(1)));
+  let mut host_limits = HashMap::<String, Arc<Semaphore>>::new();
+  let mut handles = Vec::new();
+  for request in requests {
+    let host_limit = host_limits
+      .entry(get_host(&request.url))
+      .or_insert_with(|| Arc::new(Semaphore::new(max_per_host.max(1))))
+      .clone();
+    let global_limit = global_limit.clone();
+    let client = client.clone();
+    let url = request.url.clone();
+    let handle = tokio::spawn(async move {
+      // take the host permit first so feeds waiting on a busy host don't hold up a global slot
+      let _host_permit = host_limit.acquire_owned().await.unwrap();
+      let _global_permit = global_limit.acquire_owned().await.unwrap();
+      fetch_one(&client, &request).await
+    });
+    handles.push((url, handle));
+  }
+  let mut results = Vec::new();
+  for (url, handle) in handles {
+    let result = match handle.await {
+      Ok(result) => result,
+      Err(error) => Err(FeedError::TaskFailed(error))
+    };
+    results.push((url, result));
+  }
+  Ok(results)
+}
@@ -0,0 +1,508 @@
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::name::{Namespace, QName, ResolveResult};
+use quick_xml::{NsReader, Reader, Writer};
+
+use crate::structs::*;
+
+// namespaces feeds use, by URI, with the prefix the structs expect their elements under
+const NAMESPACE_PREFIXES: &[(&str, &str)] = &[
+  ("http://search.yahoo.com/mrss/", "media"),
+  // a common typo of the above
+  ("http://search.yahoo.com/mrss", "media"),
+  ("http://purl.org/rss/1.0/modules/content/", "content"),
+  ("http://purl.org/dc/elements/1.1/", "dc"),
+  ("http://purl.org/dc/terms/", "dcterms"),
+  ("http://www.itunes.com/dtds/podcast-1.0.dtd", "itunes"),
+  ("http://www.google.com/schemas/play-podcasts/1.0", "googleplay"),
+  ("https://podcastindex.org/namespace/1.0", "podcast"),
+  ("http://www.w3.org/2005/Atom", "atom"),
+  ("http://www.w3.org/1999/02/22-rdf-syntax-ns#", "rdf"),
+  ("http://webfeeds.org/rss/1.0", "webfeeds"),
+  ("http://wellformedweb.org/CommentAPI/", "wfw"),
+  ("http://purl.org/rss/1.0/modules/syndication/", "sy"),
+  ("http://purl.org/rss/1.0/modules/slash/", "slash"),
+  ("http://www.georss.org/georss", "georss")
+];
+
+// namespaces whose elements are the feed format itself, so they go without a prefix
+const FEED_NAMESPACES: &[&str] = &[
+  // RSS 1.0
+  "http://purl.org/rss/1.0/",
+  // RSS 0.90
+  "http://my.netscape.com/rdf/simple/0.9/"
+];
+
+fn get_canonical_prefix(namespace: &[u8]) -> Option<&'static str> {
+  NAMESPACE_PREFIXES.iter()
+    .find(|(uri, _)| uri.as_bytes() == namespace)
+    .map(|(_, prefix)| *prefix)
+}
+
+// the name serde sees an element as: `prefix-name` with the prefix picked by namespace
+// (so `<m:content xmlns:m="http://search.yahoo.com/mrss/">` is `media-content` like
+// any other) and no prefix for the feed format's own elements. Prefixes that aren't
+// declared anywhere are kept as they are, since plenty of feeds forget to.
+fn get_element_name(resolved: &ResolveResult, name: QName, root_namespace: Option<&[u8]>) -> String {
+  let local_name = String::from_utf8_lossy(name.local_name().as_ref()).to_string();
+  match resolved {
+    ResolveResult::Bound(Namespace(namespace)) => {
+      if FEED_NAMESPACES.iter().any(|uri| uri.as_bytes() == *namespace) || root_namespace == Some(*namespace) {
+        return local_name;
+      }
+      match get_canonical_prefix(namespace) {
+        Some(prefix) => format!("{}-{}", prefix, local_name),
+        None => match name.prefix() {
+          Some(prefix) => format!("{}-{}", String::from_utf8_lossy(prefix.as_ref()), local_name),
+          None => local_name
+        }
+      }
+    },
+    ResolveResult::Unknown(prefix) => format!("{}-{}", String::from_utf8_lossy(prefix), local_name),
+    ResolveResult::Unbound => local_name
+  }
+}
+
+// attributes keep the colon (EX: `@rdf:about`), but also get the canonical prefix
+fn get_attribute_name(reader: &NsReader<&[u8]>, name: QName) -> String {
+  match reader.resolve_attribute(name) {
+    (ResolveResult::Bound(Namespace(namespace)), local_name) => match get_canonical_prefix(namespace) {
+      Some(prefix) => format!("{}:{}", prefix, String::from_utf8_lossy(local_name.as_ref())),
+      None => String::from_utf8_lossy(name.as_ref()).to_string()
+    },
+    _ => String::from_utf8_lossy(name.as_ref()).to_string()
+  }
+}
+
+fn rename_start(reader: &NsReader<&[u8]>, name: String, start: &BytesStart) -> Result<BytesStart<'static>, quick_xml::Error> {
+  let mut renamed = BytesStart::new(name);
+  for attribute in start.attributes() {
+    let attribute = attribute?;
+    let key = attribute.key;
+    let is_declaration = key.as_namespace_binding().is_some();
+    let name = if is_declaration {
+      String::from_utf8_lossy(key.as_ref()).to_string()
+    } else {
+      get_attribute_name(reader, key)
+    };
+    renamed.push_attribute(Attribute { key: QName(name.as_bytes()), value: attribute.value });
+  }
+  Ok(renamed.into_owned())
+}
+
+/// Rewrites the element names of an XML feed by namespace, so the structs can match
+/// `media-content`, `content-encoded`, `dc-creator` and so on whatever prefix the feed
+/// bound those namespaces to. Everything else (text, CDATA, comments) is passed through
+/// untouched.
+fn resolve_namespaces(feed_str: &str) -> Result<String, quick_xml::Error> {
+  let mut reader = NsReader::from_str(feed_str);
+  let mut writer = Writer::new(Vec::new());
+  // the namespace of the root element (the Atom one for Atom feeds) is the feed's own,
+  // so its elements go without a prefix; RSS 2.0 has none, which leaves `atom-link` as is
+  let mut root_namespace: Option<Option<Vec<u8>>> = None;
+  loop {
+    let (resolved, event) = reader.read_resolved_event()?;
+    if let (None, Event::Start(_) | Event::Empty(_)) = (&root_namespace, &event) {
+      root_namespace = match &resolved {
+        ResolveResult::Bound(Namespace(namespace)) => Some(Some(namespace.to_vec())),
+        _ => Some(None)
+      };
+    }
+    let root = root_namespace.clone().flatten();
+    let renamed = match event {
+      Event::Start(start) => {
+        let name = get_element_name(&resolved, start.name(), root.as_deref());
+        Event::Start(rename_start(&reader, name, &start)?)
+      },
+      Event::Empty(start) => {
+        let name = get_element_name(&resolved, start.name(), root.as_deref());
+        Event::Empty(rename_start(&reader, name, &start)?)
+      },
+      Event::End(end) => Event::End(BytesEnd::new(get_element_name(&resolved, end.name(), root.as_deref()))),
+      Event::Eof => break,
+      event => event
+    };
+    writer.write_event(renamed)?;
+  }
+  Ok(String::from_utf8_lossy(&writer.into_inner()).to_string())
+}
+
+/// The kinds of feed that can be parsed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedFormat {
+  Rss,
+  Atom,
+  Rdf,
+  JsonFeed
+}
+
+impl std::fmt::Display for FeedFormat {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        FeedFormat::Rss => write!(f, "RSS 2.0"),
+        FeedFormat::Atom => write!(f, "Atom"),
+        FeedFormat::Rdf => write!(f, "RSS 1.0 (RDF)"),
+        FeedFormat::JsonFeed => write!(f, "JSON Feed")
+     }
+  }
+}
+
+/// Why a feed couldn't be parsed, with as much as is known about where
+#[derive(Debug)]
+pub struct ParseError {
+  // `None` if the format couldn't be worked out at all
+  pub format: Option<FeedFormat>,
+  pub line: Option<usize>,
+  pub column: Option<usize>,
+  // the element (or for JSON Feeds the key) the error is in
+  pub element: Option<String>,
+  pub message: String
+}
+
+impl std::fmt::Display for ParseError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self.format {
+      Some(format) => write!(f, "Error parsing the feed as {}", format)?,
+      None => write!(f, "Error parsing the feed (unknown format)")?
+    }
+    if let (Some(line), Some(column)) = (self.line, self.column) {
+      write!(f, " at line {}, column {}", line, column)?;
+    }
+    match (&self.element, self.format) {
+      (Some(key), Some(FeedFormat::JsonFeed)) => write!(f, " in `{}`", key)?,
+      (Some(element), _) => write!(f, " in <{}>", element)?,
+      (None, _) => {}
+    }
+    write!(f, ": {}", self.message)
+  }
+}
+
+impl ParseError {
+  fn new(format: Option<FeedFormat>, message: String) -> ParseError {
+    ParseError { format, line: None, column: None, element: None, message }
+  }
+}
+
+fn format_from_content_type(content_type: &str) -> Option<FeedFormat> {
+  let mime_type = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+  match mime_type.as_str() {
+    "application/rss+xml" => Some(FeedFormat::Rss),
+    "application/atom+xml" => Some(FeedFormat::Atom),
+    "application/rdf+xml" => Some(FeedFormat::Rdf),
+    "application/feed+json" | "application/json" => Some(FeedFormat::JsonFeed),
+    // text/xml and the like don't say which kind of feed it is
+    _ => None
+  }
+}
+
+fn format_from_root_element(name: &str) -> Option<FeedFormat> {
+  match name {
+    "rss" => Some(FeedFormat::Rss),
+    "feed" => Some(FeedFormat::Atom),
+    "RDF" => Some(FeedFormat::Rdf),
+    _ => None
+  }
+}
+
+// the name of the first element in the document, skipping the declaration, comments,
+// doctype and so on; `None` if the document is broken before it gets to one
+fn get_root_element(feed_str: &str) -> Option<String> {
+  let mut reader = Reader::from_str(feed_str);
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(element)) | Ok(Event::Empty(element)) => {
+        // by local name, so `<RDF>` with a default namespace counts as well as `<rdf:RDF>`
+        return Some(String::from_utf8_lossy(element.local_name().as_ref()).to_string());
+      },
+      Ok(Event::Eof) | Err(_) => return None,
+      Ok(_) => {}
+    }
+  }
+}
+
+/// Works out what kind of feed `feed_str` is from its root element (or whether it is
+/// JSON), falling back on the Content-Type the server sent with it
+pub fn detect_format(feed_str: &str, content_type: Option<&str>) -> Result<FeedFormat, ParseError> {
+  let from_content_type = content_type.and_then(format_from_content_type);
+  if feed_str.trim_start_matches('\u{feff}').trim_start().starts_with('{') {
+    return Ok(FeedFormat::JsonFeed);
+  }
+  match get_root_element(feed_str) {
+    Some(root) => match format_from_root_element(&root) {
+      Some(format) => Ok(format),
+      None => Err(ParseError {
+        element: Some(root),
+        ..ParseError::new(None, String::from("the root element isn't one of <rss>, <feed> or <rdf:RDF>"))
+      })
+    },
+    None => from_content_type.ok_or(ParseError::new(None, String::from("it is neither XML with a root element nor JSON")))
+  }
+}
+
+fn get_line_and_column(feed_str: &str, position: usize) -> (usize, usize) {
+  let before = &feed_str[..position.min(feed_str.len())];
+  let line = before.matches('\n').count() + 1;
+  let column = before.len() - before.rfind('\n').map(|newline| newline + 1).unwrap_or(0) + 1;
+  (line, column)
+}
+
+// element names in deserialization errors are the rewritten ones, EX: `dc-date` for `dc:date`
+fn get_original_name(feed_str: &str, name: &str) -> String {
+  match name.split_once('-') {
+    Some((prefix, local_name)) if feed_str.contains(&format!("<{}:{}", prefix, local_name)) => format!("{}:{}", prefix, local_name),
+    _ => name.to_string()
+  }
+}
+
+/// Turns an error from deserializing an XML feed into a `ParseError`, finding where in
+/// the document it happened by reading through it again
+fn locate_xml_error(feed_str: &str, format: FeedFormat, error: quick_xml::DeError) -> ParseError {
+  let element = match &error {
+    quick_xml::DeError::UnexpectedStart(name) | quick_xml::DeError::UnexpectedEnd(name) => {
+      Some(get_original_name(feed_str, &String::from_utf8_lossy(name)))
+    },
+    _ => None
+  };
+  let mut reader = Reader::from_str(feed_str);
+  let mut open_elements = Vec::<String>::new();
+  loop {
+    let position = reader.buffer_position();
+    match reader.read_event() {
+      Ok(Event::Start(start)) => {
+        let name = String::from_utf8_lossy(start.name().as_ref()).to_string();
+        if element.as_ref() == Some(&name) {
+          let (line, column) = get_line_and_column(feed_str, position);
+          return ParseError { format: Some(format), line: Some(line), column: Some(column), element, message: error.to_string() };
+        }
+        open_elements.push(name);
+      },
+      Ok(Event::Empty(start)) => {
+        let name = String::from_utf8_lossy(start.name().as_ref()).to_string();
+        if element.as_ref() == Some(&name) {
+          let (line, column) = get_line_and_column(feed_str, position);
+          return ParseError { format: Some(format), line: Some(line), column: Some(column), element, message: error.to_string() };
+        }
+      },
+      Ok(Event::End(_)) => {
+        open_elements.pop();
+      },
+      Ok(Event::Eof) => {
+        return ParseError { element, ..ParseError::new(Some(format), error.to_string()) };
+      },
+      Ok(_) => {},
+      Err(xml_error) => {
+        // the document itself is broken, which says more than the deserializer did
+        let (line, column) = get_line_and_column(feed_str, reader.buffer_position());
+        return ParseError { format: Some(format), line: Some(line), column: Some(column), element: open_elements.pop(), message: xml_error.to_string() };
+      }
+    }
+  }
+}
+
+fn parse_xml<T: serde::de::DeserializeOwned>(feed_str: &str, format: FeedFormat) -> Result<T, ParseError> {
+  let resolved = resolve_namespaces(feed_str).map_err(|error| locate_xml_error(feed_str, format, quick_xml::DeError::InvalidXml(error)))?;
+  quick_xml::de::from_str::<T>(&resolved).map_err(|error| locate_xml_error(feed_str, format, error))
+}
+
+fn parse_json_feed(feed_str: &str) -> Result<Rss, ParseError> {
+  let json_error = |error: serde_json::Error| {
+    // serde_json puts the position at the end of the message, which would say it twice
+    let position = format!(" at line {} column {}", error.line(), error.column());
+    let message = error.to_string();
+    ParseError {
+      line: Some(error.line()),
+      column: Some(error.column()),
+      ..ParseError::new(Some(FeedFormat::JsonFeed), message.strip_suffix(&position).unwrap_or(&message).to_string())
+    }
+  };
+  let json = serde_json::from_str::<serde_json::Value>(feed_str.trim_start_matches('\u{feff}')).map_err(json_error)?;
+  let version = json.get("version").and_then(|version| version.as_str()).unwrap_or("");
+  if !version.starts_with("https://jsonfeed.org/version/") {
+    return Err(ParseError {
+      element: Some(String::from("version")),
+      ..ParseError::new(Some(FeedFormat::JsonFeed), format!("`{}` isn't a JSON Feed version", version))
+    });
+  }
+  serde_json::from_value::<JsonFeed>(json)
+    .map(|json_feed| json_feed.into_rss())
+    .map_err(|error| ParseError::new(Some(FeedFormat::JsonFeed), error.to_string()))
+}
+
+/// Parses a feed of any supported format into `Rss`, using the Content-Type the
+/// server sent with it (if any) when the document itself doesn't make it clear
+pub fn parse_feed(feed_str: &str, content_type: Option<&str>) -> Result<Rss, ParseError> {
+  match detect_format(feed_str, content_type)? {
+    FeedFormat::Rss => parse_xml::<Rss>(feed_str, FeedFormat::Rss),
+    FeedFormat::Atom => parse_xml::<Feed>(feed_str, FeedFormat::Atom).map(|feed| feed.into_rss()),
+    FeedFormat::Rdf => parse_xml::<Rdf>(feed_str, FeedFormat::Rdf).map(|rdf| rdf.into_rss()),
+    FeedFormat::JsonFeed => parse_json_feed(feed_str)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+<channel><title>Feed</title><link>https://feed.example/</link>
+<item><guid>https://feed.example/1</guid><title>One</title><link>https://feed.example/1</link>
+<description>summary</description><content:encoded><![CDATA[<p>full</p>]]></content:encoded>
+<media:content url="https://feed.example/a.png" type="image/png" medium="image"/>
+<pubDate>Wed, 16 Aug 2023 18:00:00 GMT</pubDate></item>
+</channel></rss>"#;
+
+  fn first_item(rss: &Rss) -> &Item {
+    &rss.channel.item[0]
+  }
+
+  #[test]
+  fn parses_rdf() {
+    let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns="http://purl.org/rss/1.0/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<channel rdf:about="https://r.example/"><title>R</title><link>https://r.example/</link><description>d</description></channel>
+<item rdf:about="https://r.example/1"><title>One</title><link>https://r.example/1</link><dc:date>2023-08-17T03:00:00+09:00</dc:date><dc:creator>Emma</dc:creator></item>
+<item rdf:about="https://r.example/2"><title>Two</title><link>https://r.example/2</link></item>
+</rdf:RDF>"#;
+    assert_eq!(detect_format(rdf, None).unwrap(), FeedFormat::Rdf);
+    let rss = parse_feed(rdf, None).unwrap();
+    let item = first_item(&rss);
+    assert_eq!(rss.channel.title, "R");
+    assert_eq!(rss.channel.item.len(), 2);
+    assert_eq!(item.guid, "https://r.example/1");
+    assert_eq!(item.author.as_ref().map(|author| author.name.as_str()), Some("Emma"));
+    // dc:date is turned into an RSS date
+    assert_eq!(item.pub_date.as_deref(), Some("Wed, 16 Aug 2023 18:00:00 +0000"));
+  }
+
+  #[test]
+  fn parses_json_feed() {
+    let json = r#"{"version":"https://jsonfeed.org/version/1.1","title":"J","items":[
+      {"id":"1","url":"https://j.example/1","title":"One","summary":"hi","content_html":"<p>hi</p>","image":"https://j.example/1.png",
+        "date_published":"2023-08-16T18:00:00Z","authors":[{"name":"Emma","url":"https://j.example/"}],
+        "attachments":[{"url":"https://j.example/1.mp3","mime_type":"audio/mpeg","size_in_bytes":1024}]},
+      {"id":2,"content_text":"no title"}]}"#;
+    assert_eq!(detect_format(json, None).unwrap(), FeedFormat::JsonFeed);
+    let rss = parse_feed(json, None).unwrap();
+    assert_eq!(rss.channel.item.len(), 2);
+    let item = first_item(&rss);
+    assert_eq!(item.link.as_deref(), Some("https://j.example/1"));
+    assert_eq!(item.description.as_deref(), Some("hi"));
+    assert_eq!(item.content_encoded.as_deref(), Some("<p>hi</p>"));
+    assert_eq!(item.imageurl.as_deref(), Some("https://j.example/1.png"));
+    assert_eq!(item.author.as_ref().map(|author| author.name.as_str()), Some("Emma"));
+    assert_eq!(item.media_content.as_ref().unwrap()[0].url, "https://j.example/1.mp3");
+    assert_eq!(item.get_published_timestamp(), Some(1692208800));
+    // ids can be numbers too
+    assert_eq!(rss.channel.item[1].guid, "2");
+    assert_eq!(rss.channel.item[1].description.as_deref(), Some("no title"));
+  }
+
+  #[test]
+  fn rejects_json_that_isnt_a_json_feed() {
+    let error = parse_feed(r#"{"version":"1.0","items":[]}"#, None).unwrap_err();
+    assert_eq!(error.format, Some(FeedFormat::JsonFeed));
+    assert_eq!(error.element.as_deref(), Some("version"));
+  }
+
+  #[test]
+  fn detects_the_format_from_the_root_element_first() {
+    assert_eq!(detect_format(RSS, Some("application/atom+xml")).unwrap(), FeedFormat::Rss);
+    assert_eq!(detect_format("", Some("application/rss+xml; charset=utf-8")).unwrap(), FeedFormat::Rss);
+    assert!(detect_format("<html></html>", Some("text/html")).unwrap_err().element.as_deref() == Some("html"));
+    assert!(detect_format("", None).unwrap_err().format.is_none());
+  }
+
+  #[test]
+  fn reports_where_broken_xml_breaks() {
+    let feed = "<rss version=\"2.0\"><channel><title>T</title>\n<item><title>x</titl></item>\n</channel></rss>";
+    let error = parse_feed(feed, None).unwrap_err();
+    assert_eq!(error.format, Some(FeedFormat::Rss));
+    assert_eq!(error.line, Some(2));
+    assert!(error.to_string().starts_with("Error parsing the feed as RSS 2.0 at line 2"), "{error}");
+  }
+
+  #[test]
+  fn reports_where_json_breaks() {
+    let error = parse_feed("{\"version\":\n\"https://jsonfeed.org/version/1.1\",}", None).unwrap_err();
+    assert_eq!((error.line, error.column), (Some(2), Some(36)));
+    // the position is only said once
+    assert_eq!(error.to_string().matches("line 2").count(), 1);
+  }
+
+  #[test]
+  fn parses_rss() {
+    let rss = parse_feed(RSS, None).unwrap();
+    assert_eq!(rss.channel.title, "Feed");
+    let item = first_item(&rss);
+    assert_eq!(item.guid, "https://feed.example/1");
+    assert_eq!(item.title.as_deref(), Some("One"));
+    assert_eq!(item.content_encoded.as_deref(), Some("<p>full</p>"));
+    assert_eq!(item.media_content.as_ref().unwrap()[0].url, "https://feed.example/a.png");
+  }
+
+  #[test]
+  fn resolves_rebound_prefixes() {
+    // the same namespaces bound to other prefixes, and the usual prefixes bound to nothing in particular
+    let feed = RSS
+      .replace("xmlns:media=", "xmlns:m=")
+      .replace("xmlns:content=", "xmlns:c=")
+      .replace("content:encoded", "c:encoded")
+      .replace("<media:content", "<m:content");
+    let rss = parse_feed(&feed, None).unwrap();
+    let item = first_item(&rss);
+    assert_eq!(item.content_encoded.as_deref(), Some("<p>full</p>"));
+    assert_eq!(item.media_content.as_ref().unwrap()[0].url, "https://feed.example/a.png");
+  }
+
+  #[test]
+  fn ignores_prefixes_bound_to_other_namespaces() {
+    // `m` is bound to something that isn't Media RSS, so its `content` isn't media
+    let feed = RSS
+      .replace("xmlns:media=\"http://search.yahoo.com/mrss/\"", "xmlns:m=\"https://not-media.example/\"")
+      .replace("<media:content", "<m:content");
+    let rss = parse_feed(&feed, None).unwrap();
+    assert!(first_item(&rss).media_content.is_none());
+    assert_eq!(first_item(&rss).content_encoded.as_deref(), Some("<p>full</p>"));
+  }
+
+  #[test]
+  fn keeps_undeclared_prefixes() {
+    let feed = RSS
+      .replace(" xmlns:media=\"http://search.yahoo.com/mrss/\"", "")
+      .replace(" xmlns:content=\"http://purl.org/rss/1.0/modules/content/\"", "");
+    let rss = parse_feed(&feed, None).unwrap();
+    let item = first_item(&rss);
+    assert_eq!(item.content_encoded.as_deref(), Some("<p>full</p>"));
+    assert_eq!(item.media_content.as_ref().unwrap()[0].url, "https://feed.example/a.png");
+  }
+
+  #[test]
+  fn leaves_text_and_cdata_alone() {
+    let feed = RSS.replace("<description>summary</description>", "<description><![CDATA[about <media:content> and <x:y>]]></description>");
+    let rss = parse_feed(&feed, None).unwrap();
+    assert_eq!(first_item(&rss).description.as_deref(), Some("about <media:content> and <x:y>"));
+  }
+
+  #[test]
+  fn parses_atom_with_a_prefixed_or_default_namespace() {
+    let atom = r#"<?xml version="1.0" encoding="utf-8"?>
+<a:feed xmlns:a="http://www.w3.org/2005/Atom" xmlns:mm="http://search.yahoo.com/mrss/">
+<a:title>Atom</a:title><a:subtitle>s</a:subtitle><a:id>urn:x</a:id><a:updated>2023-08-16T18:00:00Z</a:updated>
+<a:author><a:name>Me</a:name><a:uri>https://me.example/</a:uri></a:author>
+<a:entry><a:id>urn:x:1</a:id><a:title>E1</a:title><a:updated>2023-08-16T18:00:00Z</a:updated>
+<a:author><a:name>Me</a:name><a:uri>https://me.example/</a:uri></a:author>
+<a:summary>hi</a:summary><a:content>hello</a:content><a:link rel="alternate" href="https://x.example/1"/>
+<mm:content url="https://x.example/v.mp4" type="video/mp4" medium="video"/></a:entry>
+</a:feed>"#;
+    for feed in [atom.to_string(), atom.replace("a:", "").replace("xmlns:a=", "xmlns=")] {
+      assert_eq!(detect_format(&feed, None).unwrap(), FeedFormat::Atom);
+      let rss = parse_feed(&feed, None).unwrap();
+      let item = first_item(&rss);
+      assert_eq!(item.guid, "urn:x:1");
+      assert_eq!(item.title.as_deref(), Some("E1"));
+      assert_eq!(item.link.as_deref(), Some("https://x.example/1"));
+      assert_eq!(item.media_content.as_ref().unwrap()[0].url, "https://x.example/v.mp4");
+    }
+  }
+}
@@ -18,30 +18,19 @@ impl std::fmt::Display for DownloadImageError {
   }
 }
 
-pub enum DownloadImageOptions {
-  Url(String),
-  UrlAndOutputDir(String, String)
-}
-
-pub async fn download_image(params: DownloadImageOptions) -> Result<String, DownloadImageError> {
-  let (url, out_dir) = match params {
-    DownloadImageOptions::Url(url) => {
-      (url, String::from("media/"))
-    }, 
-    DownloadImageOptions::UrlAndOutputDir(url, out_dir) => {
-      (url, out_dir)
-    }
-  }; 
+/// Downloads `url` into `out_dir` (unless it is already there) and returns its path relative to `out_dir`
+#[allow(clippy::single_match)]
+pub async fn download_image(url: &str, out_dir: &str) -> Result<String, DownloadImageError> {
   let client = reqwest::Client::new();
-  match client.get(&url).send().await {
+  match client.get(url).send().await {
     Ok(response) => {
       let file_name = url.replace("https://", "").replace("http://", "");
-      let path_str = format!("{}{}", out_dir, file_name);
-      let path = std::path::Path::new(&path_str);
+      let path_buf = std::path::Path::new(out_dir).join(&file_name);
+      let path = path_buf.as_path();
       let parent = path.parent();
       match parent {
         Some(parent) => {
-          match std::fs::create_dir_all(&parent) {
+          match std::fs::create_dir_all(parent) {
             Ok(_) => {},
             Err(error) => { return Err(DownloadImageError::FileOpen(error)); }
           }
@@ -50,8 +39,8 @@ pub async fn download_image(params: DownloadImageOptions) -> Result<String, Down
 
         }
       }
-      if !std::path::Path::new(&path_str).exists() {
-        let mut file = match File::create(path_str) {
+      if !path.exists() {
+        let mut file = match File::create(path) {
           Ok(file) => file,
           Err(error) => {
             return Err(DownloadImageError::FileOpen(error));
@@ -61,7 +50,7 @@ pub async fn download_image(params: DownloadImageOptions) -> Result<String, Down
         while let Some(chunk) = stream.next().await {
           match chunk {
             Ok(chunk) => {
-              match file.write(&chunk) {
+              match file.write_all(&chunk) {
                 Ok(_) => {},
                 Err(error) => {
                   return Err(DownloadImageError::FileWrite(error))
@@ -84,3 +73,12 @@ pub async fn download_image(params: DownloadImageOptions) -> Result<String, Down
     }
   }
 }
+
+/// Turns text into something usable in a file name or URL, EX: `Emma's Art!` -> `emma-s-art`
+pub fn slugify(text: &str) -> String {
+  let slug = text.to_lowercase()
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+    .collect::<String>();
+  slug.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-")
+}
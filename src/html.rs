@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use chrono::TimeZone;
+use regex::Regex;
+
+use crate::structs::*;
+
+// the templates used unless `--templates` has a file of the same name; `{{name}}` is
+// replaced with the value HTML escaped and `{{{name}}}` with it as is
+const PAGE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+<style>
+body { max-width: 40em; margin: 0 auto; padding: 1em; font-family: sans-serif; line-height: 1.5; }
+article { border-bottom: 1px solid #ccc; padding: 1em 0; }
+img, video { max-width: 100%; height: auto; }
+nav { display: flex; justify-content: space-between; padding: 1em 0; }
+</style>
+</head>
+<body>
+<header><h1><a href="{{root}}index.html">{{site_title}}</a></h1></header>
+<main>
+{{{content}}}
+</main>
+{{{pagination}}}
+</body>
+</html>
+"#;
+
+const ITEM_TEMPLATE: &str = r#"<article>
+<h2><a href="{{permalink}}">{{title}}</a></h2>
+<p><a href="{{author_link}}">{{author_name}}</a> · <time datetime="{{datetime}}">{{date}}</time> · <a href="{{link}}">original</a></p>
+{{{content}}}
+{{{media}}}
+</article>
+"#;
+
+/// The templates `output-html` renders pages and items with
+pub struct Templates {
+  page: String,
+  item: String
+}
+
+impl Templates {
+  /// The built in templates, with any of `page.html` and `item.html` in `dir` in their place
+  pub fn load(dir: Option<&str>) -> Result<Templates, std::io::Error> {
+    let load_one = |file_name: &str, default: &str| -> Result<String, std::io::Error> {
+      match dir.map(|dir| Path::new(dir).join(file_name)) {
+        Some(path) if path.exists() => std::fs::read_to_string(path),
+        _ => Ok(default.to_string())
+      }
+    };
+    Ok(Templates {
+      page: load_one("page.html", PAGE_TEMPLATE)?,
+      item: load_one("item.html", ITEM_TEMPLATE)?
+    })
+  }
+}
+
+static PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\{\{(\{?)\s*([a-z_]+)\s*\}?\}\}"#).unwrap());
+
+fn render(template: &str, values: &HashMap<&str, String>) -> String {
+  PLACEHOLDER_REGEX.replace_all(template, |captures: &regex::Captures| {
+    let value = values.get(&captures[2]).map(|value| value.as_str()).unwrap_or("");
+    if captures[1].is_empty() {
+      quick_xml::escape::escape(value).to_string()
+    } else {
+      value.to_string()
+    }
+  }).to_string()
+}
+
+// a stable hash for permalinks, since `DefaultHasher` may change between Rust releases (FNV-1a)
+fn stable_hash(text: &str) -> u64 {
+  text.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// The path of an item's permalink page, relative to the output directory; made from
+/// nothing but the guid so it stays the same when the item is edited
+pub fn get_permalink(item: &Item) -> PathBuf {
+  Path::new("items").join(format!("{:016x}.html", stable_hash(&item.guid)))
+}
+
+/// The pages under `page/` and `items/` in `site_dir` left from an earlier run that
+/// aren't among `files` any more (like the permalinks of pruned items)
+pub fn get_stale_files(site_dir: &Path, files: &[(PathBuf, String)]) -> Vec<PathBuf> {
+  let current = files.iter().map(|(path, _)| site_dir.join(path)).collect::<std::collections::HashSet<_>>();
+  ["page", "items"].iter()
+    .filter_map(|dir| std::fs::read_dir(site_dir.join(dir)).ok())
+    .flatten()
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().is_some_and(|extension| extension == "html") && !current.contains(path))
+    .collect()
+}
+
+fn get_page_path(page: usize) -> PathBuf {
+  if page == 1 {
+    PathBuf::from("index.html")
+  } else {
+    Path::new("page").join(format!("{}.html", page))
+  }
+}
+
+fn get_link(path: &Path, root: &str) -> String {
+  format!("{}{}", root, path.to_string_lossy().replace('\\', "/"))
+}
+
+fn render_item(item: &Item, templates: &Templates, root: &str) -> String {
+  let content = item.content_encoded.clone().or(item.description.clone()).unwrap_or_default();
+  // media that isn't already part of the content
+  let media = item.media_content.iter()
+    .flatten()
+    .filter(|media| !content.contains(&media.url))
+    .map(|media| media.into_html())
+    .collect::<Vec<_>>()
+    .join(" ");
+  let datetime = item.timestamp.and_then(|timestamp| chrono::Utc.timestamp_opt(timestamp, 0).single());
+  let values = HashMap::from([
+    ("title", item.title.clone().unwrap_or_default()),
+    ("link", item.link.clone().unwrap_or_default()),
+    ("permalink", get_link(&get_permalink(item), root)),
+    ("author_name", item.author.as_ref().map(|author| author.name.clone()).unwrap_or_default()),
+    ("author_link", item.author.as_ref().map(|author| author.uri.clone()).unwrap_or_default()),
+    ("date", datetime.map(|datetime| datetime.format("%-d %B %Y").to_string()).unwrap_or_default()),
+    ("datetime", datetime.map(|datetime| datetime.to_rfc3339()).unwrap_or_default()),
+    ("summary", item.description.clone().unwrap_or_default()),
+    ("content", content),
+    ("media", media)
+  ]);
+  render(&templates.item, &values)
+}
+
+fn render_page(settings: &Settings, templates: &Templates, title: &str, content: String, pagination: String, root: &str) -> String {
+  let values = HashMap::from([
+    ("title", title.to_string()),
+    ("site_title", settings.title.clone()),
+    ("site_link", settings.link.clone()),
+    ("root", root.to_string()),
+    ("content", content),
+    ("pagination", pagination)
+  ]);
+  render(&templates.page, &values)
+}
+
+fn render_pagination(page: usize, pages: usize, root: &str) -> String {
+  if pages <= 1 {
+    return String::new();
+  }
+  let newer = if page > 1 {
+    format!("<a rel=\"prev\" href=\"{}\">Newer</a>", get_link(&get_page_path(page - 1), root))
+  } else {
+    String::from("<span></span>")
+  };
+  let older = if page < pages {
+    format!("<a rel=\"next\" href=\"{}\">Older</a>", get_link(&get_page_path(page + 1), root))
+  } else {
+    String::from("<span></span>")
+  };
+  format!("<nav>{}<span>Page {} of {}</span>{}</nav>", newer, page, pages, older)
+}
+
+impl Db {
+  /// The aggregated feed as a static site: pages of `per_page` items (the first as
+  /// `index.html`, then `page/2.html` and so on) and a page per item under `items/`;
+  /// returned as paths relative to the output directory along with their contents
+  pub fn output_html(&self, templates: &Templates, per_page: usize) -> Vec<(PathBuf, String)> {
+    let items = self.prepare_items();
+    let mut files = Vec::new();
+    let chunks = items.chunks(per_page.max(1)).collect::<Vec<_>>();
+    let pages = chunks.len().max(1);
+    for page in 1..=pages {
+      let root = if page == 1 { "" } else { "../" };
+      let content = chunks.get(page - 1)
+        .map(|chunk| chunk.iter().map(|item| render_item(item, templates, root)).collect::<Vec<_>>().join("\n"))
+        .unwrap_or_default();
+      let title = if page == 1 { self.settings.title.clone() } else { format!("{} (page {})", self.settings.title, page) };
+      files.push((get_page_path(page), render_page(&self.settings, templates, &title, content, render_pagination(page, pages, root), root)));
+    }
+    for item in items.iter() {
+      let content = render_item(item, templates, "../");
+      let title = item.title.clone().unwrap_or(self.settings.title.clone());
+      files.push((get_permalink(item), render_page(&self.settings, templates, &title, content, String::new(), "../")));
+    }
+    files
+  }
+}
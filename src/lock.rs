@@ -0,0 +1,77 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use fs4::fs_std::FileExt;
+
+#[derive(Debug)]
+pub enum LockError {
+  Held(PathBuf, Option<u32>),
+  FileCreateError(std::io::Error),
+  LockError(std::io::Error)
+}
+
+impl std::fmt::Display for LockError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        LockError::Held(path, Some(pid)) => write!(f, "The db is locked by another run (PID {}) holding {}", pid, path.display()),
+        LockError::Held(path, None) => write!(f, "The db is locked by another run holding {}", path.display()),
+        LockError::FileCreateError(error) => write!(f, "Error creating lock file: {}", error),
+        LockError::LockError(error) => write!(f, "Error locking lock file: {}", error)
+     }
+  }
+}
+
+/// An advisory lock held for the whole load-modify-save cycle of a run,
+/// so overlapping runs can't clobber each other's changes to the db.
+///
+/// The lock is an OS file lock (`flock` on unix) on a file next to the db, which
+/// also holds the PID of the run holding it. The OS releases it when the file is
+/// closed, so a run that crashes can't leave a stale lock behind.
+pub struct DbLock {
+  // never read, the lock lasts as long as the file is open
+  _file: File
+}
+
+fn read_pid(path: &PathBuf) -> Option<u32> {
+  let mut contents = String::new();
+  File::open(path).ok()?.read_to_string(&mut contents).ok()?;
+  contents.trim().parse::<u32>().ok()
+}
+
+impl DbLock {
+  /// Takes the lock for the db at `db_path`, waiting up to `timeout` for another run to release it
+  pub async fn acquire(db_path: &str, timeout: Duration) -> Result<DbLock, LockError> {
+    let path = PathBuf::from(format!("{db_path}.lock"));
+    // the file is left in place between runs; deleting it would let a waiter lock
+    // the old file while the next run locks a new one
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(&path)
+      .map_err(LockError::FileCreateError)?;
+    let started = Instant::now();
+    loop {
+      match file.try_lock_exclusive() {
+        Ok(true) => {
+          // only used to say who is holding the lock when another run gives up on it
+          file.set_len(0)
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .map_err(LockError::FileCreateError)?;
+          return Ok(DbLock { _file: file });
+        },
+        Ok(false) => {
+          if started.elapsed() >= timeout {
+            return Err(LockError::Held(path.clone(), read_pid(&path)));
+          }
+          tokio::time::sleep(Duration::from_millis(250)).await;
+        },
+        Err(error) => {
+          return Err(LockError::LockError(error));
+        }
+      }
+    }
+  }
+}
@@ -1,337 +1,153 @@
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod dates;
+pub mod dedup;
+pub mod encoding;
+pub mod fetch;
+pub mod formats;
 pub mod helpers;
+pub mod html;
+pub mod lock;
+pub mod migrations;
+pub mod retention;
+pub mod sqlite;
+pub mod storage;
 pub mod structs;
+#[cfg(test)]
+mod test_helpers;
 
-use std::process::{Command, Stdio};
-use std::fs::File;
-use std::io::{Write, Read};
-use regex::Regex;
+use clap::Parser;
 use structs::*;
 
-use crate::helpers::{DownloadImageOptions, download_image};
+use crate::cli::{Cli, Commands, StorageKind};
+use crate::config::ConfigFile;
+use crate::lock::DbLock;
 
-fn clean(input: &str) -> String {
-  let re = Regex::new(r#"<(/?)([a-zA-Z_][a-zA-Z0-9_]*):([a-zA-Z_][a-zA-Z0-9_]*) *([^>]*)>"#).unwrap();
-  format!("{}", re.replace_all(input, r#"<$1$2-$3 $4>"#))
-}
-
-#[derive(Debug)]
-enum FeedError {
-  Reqwest(reqwest::Error),
-  NonSuccessfulStatusCode(reqwest::StatusCode)
-}
-
-impl std::fmt::Display for FeedError {
-  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-     match self {
-        FeedError::NonSuccessfulStatusCode(code) => write!(f, "Request returned non-successful status code: {}", code),
-        FeedError::Reqwest(error) => write!(f, "Error making request: {}", error)
-     }
-  }
-}
-
-async fn fetch_feed(url: &str) -> Result<String, FeedError> {
-  let client = reqwest::Client::new();
-  let response = match client.get(url).send().await {
-    Ok(result) => result,
-    Err(err) => return Err(FeedError::Reqwest(err))
-  };
-  match response.status() {
-    reqwest::StatusCode::OK => {
-      response.text().await.map_err(|e| FeedError::Reqwest(e))
-    },
-    _ => {
-      Err(FeedError::NonSuccessfulStatusCode(response.status()))
-    }
-  }
-}
-
-#[derive(Debug)]
-enum DbCreateError {
-  FileCreateError(std::io::Error),
-  FormattingError(serde_json::error::Error),
-  FileWriteError(std::io::Error)
-}
-
-impl std::fmt::Display for DbCreateError {
-  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-     match self {
-        DbCreateError::FileCreateError(error) => write!(f, "Error creating file: {}", error),
-        DbCreateError::FormattingError(error) => write!(f, "Error formatting file: {}", error),
-        DbCreateError::FileWriteError(error) => write!(f, "Error writing to file: {}", error)
-     }
+#[tokio::main]
+async fn main() {
+  env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+  let cli = Cli::parse();
+  let lock_timeout = std::time::Duration::from_secs(cli.lock_timeout);
+  if let Commands::Migrate { to, force } = &cli.command {
+    let from_path = cli.db.clone().unwrap_or(String::from(StorageKind::Json.default_path()));
+    let to_path = match to {
+      Some(to) => to.clone(),
+      None => std::path::Path::new(&from_path).with_extension("sqlite").to_string_lossy().to_string()
+    };
+    let from = StorageKind::Json.open(&from_path);
+    let to = StorageKind::Sqlite.open(&to_path);
+    let locks = match DbLock::acquire(from.path(), lock_timeout).await {
+      Ok(from_lock) => DbLock::acquire(to.path(), lock_timeout).await.map(|to_lock| (from_lock, to_lock)),
+      Err(error) => Err(error)
+    };
+    let migrated = match locks {
+      Ok(_locks) => commands::migrate(from.as_ref(), to.as_ref(), &cli.config_path(), *force),
+      Err(error) => {
+        log::error!("❌ {}", error);
+        false
+      }
+    };
+    std::process::exit(if migrated { 0 } else { 1 });
   }
-}
-
-fn save_db(db: &Db, path: &str) -> Result<(),DbCreateError> {
-  let mut file = match File::create(path) {
-    Ok(result) => result,
+  let storage = cli.storage.open(&cli.db_path());
+  // held until the end of main so nothing else can touch the db between loading and saving it
+  let _lock = match DbLock::acquire(storage.path(), lock_timeout).await {
+    Ok(lock) => lock,
     Err(error) => {
-      return Err(DbCreateError::FileCreateError(error));
+      log::error!("❌ {}", error);
+      std::process::exit(1);
     }
   };
-  let formatted_db = match serde_json::to_string_pretty(&db) {
+  let mut db = match storage.load() {
     Ok(db) => db,
-    Err(error) => {
-      return Err(DbCreateError::FormattingError(error))
-    }
-  };
-  write!(file, "{}", formatted_db)
-    .map_err(|error| DbCreateError::FileWriteError(error))
-}
-
-#[derive(Debug)]
-enum GetDbError {
-  FileOpenError(std::io::Error),
-  FileReadError(std::io::Error),
-  JsonDeserializeError(serde_json::error::Error)
-}
-
-impl std::fmt::Display for GetDbError {
-  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-     match self {
-        GetDbError::FileOpenError(error) => write!(f, "Error opening file: {}", error),
-        GetDbError::FileReadError(error) => write!(f, "Error reading file: {}", error),
-        GetDbError::JsonDeserializeError(error) => write!(f, "Error deserializing JSON from file: {}", error)
-     }
-  }
-}
-
-fn get_db(path: &str) -> Result<Db, GetDbError> {
-  let mut f = match File::open(path) {
-    Ok(f) => f,
-    Err(error) => {
-      return Err(GetDbError::FileOpenError(error))
-    }
-  };
-  let mut output = String::from("");
-  match f.read_to_string(&mut output) {
-    Ok(_) => {
-      match serde_json::from_str::<Db>(&output) {
-        Ok(result) => {
-          Ok(result)
-        },
-        Err(error) => {
-          Err(GetDbError::JsonDeserializeError(error))
-        }
-      }
+    Err(error) if error.is_not_found() => {
+      log::info!("No db found, starting a new one");
+      Db::new()
     },
     Err(error) => {
-      Err(GetDbError::FileReadError(error))
+      // carrying on with an empty db would overwrite the existing one on save
+      log::error!("❌ {}", error);
+      log::error!("❌ Refusing to continue so {} isn't overwritten; fix it or restore it from a backup", storage.path());
+      std::process::exit(1);
     }
-  }
-}
-
-#[tokio::main]
-async fn main() {
-  env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
-  let mut db = match get_db("db.json") {
-    Ok(db) => db,
+  };
+  let mut config_file = match ConfigFile::load_or_migrate(&cli.config_path(), &mut db) {
+    Ok(config_file) => config_file,
     Err(error) => {
       log::error!("❌ {}", error);
-      Db::new()
+      std::process::exit(1);
     }
   };
-  let args: Vec<String> = std::env::args().collect();
   let pkg_version = env!("CARGO_PKG_VERSION");
   println!("Syndication Junction v{pkg_version}");
-  if args.len() > 1 {
-    match args[1].as_str() {
-      "fetch" => {
-        if args.len() > 2 {
-          let feeds = args[2..args.len()].to_vec();
-          for feed in feeds {
-            match fetch_feed(&feed).await {
-              Ok(mut feed_str) => {
-                if db.rss.contains_key(&feed) {
-                  if db.rss[&feed].manipulate_input != "" {
-                    let cmd = db.rss[&feed].manipulate_input.split(" ").collect::<Vec<_>>();
-                    let mut command = Command::new(cmd[0]);
-                    command.args(&cmd[1..cmd.len()])
-                        .stdin(Stdio::piped())
-                        .stdout(Stdio::piped());
-                    let child = command.spawn().unwrap();
-                    match child.stdin.unwrap().write_all(&feed_str.as_bytes()[..]) {
-                      Ok(_) => { },
-                      Err(error) => {
-                        log::error!("Failed to write to modification shell script: {}", error);
-                      }
-                    }
-                    let mut s = String::new();
-                    match child.stdout.unwrap().read_to_string(&mut s) {
-                        Err(why) => log::error!("couldn't read wc stdout: {}", why),
-                        Ok(_) => { 
-                          feed_str = format!("{}", s);
-                        }
-                    }
-                  }
-                }
-                
-                let rss = match quick_xml::de::from_str::<Rss>(&clean(&feed_str)) {
-                  Ok(rss) => {
-                    Some(rss)
-                  },
-                  Err(error) => {
-                    match quick_xml::de::from_str::<Feed>(&clean(&feed_str)) {
-                      Ok(feed) => {
-                        Some(feed.into_rss())
-                      },
-                      Err(derror) => {
-                        log::error!("❌ No matching format found for the RSS file: {} {}", error, derror);
-                        None
-                      }
-                    }
-                  }
-                };
-                match rss {
-                  Some(rss) => {
-                    let feed_options = if db.rss.contains_key(&feed) {
-                      let a = db.rss.get(&feed).map(|e|Some(e.to_owned()));
-                      db.rss.remove(&feed);
-                      a.map(|a| a.map(|mut options| {
-                        options.rss.channel.item.update_list_by_guids(rss.channel.item.clone());
-                        options.rss.channel.title = rss.channel.title.clone();
-                        options.rss.channel.link = rss.channel.link.clone();
-                        Some(options)
-                      })).unwrap_or(None).unwrap_or(None)
-                    } else {
-                      Some(FeedOptions {
-                        rss: rss.clone(),
-                        manipulate_input: "".to_string(),
-                        retain_all_entries: true,
-                        title: rss.channel.title.clone(),
-                        link: rss.channel.link.clone()
-                      })
-                    };
-                    match feed_options {
-                      Some(options) => {
-                        db.rss.insert(feed, options);
-                      },
-                      None => {}
-                    }
-                  },
-                  None => {}
-                }
-              },
-              Err(err) => {
-                log::error!("❌ {feed}: {}", err);
-              }
-            }
-          }
-          match save_db(&db, "db.json") {
-            Ok(()) => {
-              log::info!("Db sucessfully saved!");
-            },
-            Err(error) => {
-              log::error!("❌ {}", error);
-            }
-          }
-        }
-      },
-      "output-rss" => {
-        let output_file_name = if args.len() > 2 {
-          args[2].clone()
-        } else {
-          String::from("rss.xml")
-        };
-        let host_name = if args.len() > 3 {
-          Some(args[3].clone())
-        } else {
-          None
-        };
-        let mut f = File::create(output_file_name).unwrap();
-        match host_name {
-          Some(host_name) => {
-            for (_, feed_options) in db.rss.iter_mut() {
-              for item in feed_options.rss.channel.item.iter_mut() {
-                match item.media_content.as_mut() {
-                  Some(media_content) => {
-                    for content_item in media_content.iter_mut() {
-                      match download_image(DownloadImageOptions::Url(content_item.url.clone())).await {
-                        Ok(_) => {
-                          content_item.url = content_item.url.replace("https://", &format!("{}/media/", &host_name));
-                        },
-                        Err(error) => {
-                          log::error!("{}", error);
-                        }
-                      }
-                    }
-                  },
-                  None => {}
-                }
-                match item.description.as_mut() {
-                  Some(description) => {
-                    let description_html_frag = scraper::Html::parse_fragment(description);
-                    let images_selector = scraper::Selector::parse("img").unwrap();
-                    let images = description_html_frag.select(&images_selector).collect::<Vec::<_>>();
-                    for image in images {
-                      match image.value().attr("src") {
-                        Some(src) => {
-                          match download_image(DownloadImageOptions::Url(src.to_string())).await {
-                            Ok(_) => {
-                              *description = description.replace(src, &src.replace("https://", &format!("{}/media/", &host_name)).replace("%", "%25"));
-                            },
-                            Err(error) => {
-                              log::error!("{}", error);
-                            }
-                          }
-                        },
-                        None => {}
-                      };
-    
-                    }
-                  },
-                  None => {}
-                }
-                match item.content_encoded.as_mut() {
-                  Some(description) => {
-                    let description_html_frag = scraper::Html::parse_fragment(description);
-                    let images_selector = scraper::Selector::parse("img").unwrap();
-                    let images = description_html_frag.select(&images_selector).collect::<Vec::<_>>();
-                    for image in images {
-                      match image.value().attr("src") {
-                        Some(src) => {
-                          match download_image(DownloadImageOptions::Url(src.to_string())).await {
-                            Ok(_) => {
-                              *description = description.replace(src, &src.replace("https://", &format!("{}/media/", &host_name)));
-                            },
-                            Err(error) => {
-                              log::error!("{}", error);
-                            }
-                          }
-                        },
-                        None => {}
-                      };
-    
-                    }
-                  },
-                  None => {}
-                }
-              }
-            }
-            
-          },
-          None => {
-
-          }
-        }
-        let rss_output = format!(r#"{}"#, db.output_rss().expect("Failed outputing feed to RSS").replace("<content:encoded/>", ""));
-        match write!(f, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}", &rss_output) {
-          Ok(()) => {
-            log::info!("✅ Sucessfully wrote RSS file");
-          },
-          Err(error) => {
-            log::error!("❌ {}", error);
-          }
-        }
+  // set when the command couldn't do what was asked, like showing a feed that isn't subscribed
+  let mut failed = false;
+  let modified = match cli.command {
+    Commands::Fetch { urls, only, tags } => {
+      commands::fetch(&mut db, urls, only, tags).await;
+      true
+    },
+    Commands::Add { url, title, link, manipulate_input, retention, tags } => {
+      commands::add(&mut db, url, title, link, manipulate_input, retention, tags);
+      true
+    },
+    Commands::Prune { urls, dry_run } => {
+      commands::prune(&mut db, urls, dry_run)
+    },
+    Commands::Remove { url } => {
+      let removed = commands::remove(&mut db, &url);
+      failed = !removed;
+      removed
+    },
+    Commands::List => {
+      commands::list(&db);
+      false
+    },
+    Commands::Show { url } => {
+      failed = !commands::show(&db, &url);
+      false
+    },
+    Commands::History { guid } => {
+      failed = !commands::history(&db, &guid);
+      false
+    },
+    Commands::OutputRss { output_file_name, host_name } => {
+      commands::output_rss(&mut db, output_file_name, host_name, &cli.media_dir).await;
+      false
+    },
+    Commands::OutputAtom { output_file_name, host_name } => {
+      commands::output_atom(&mut db, output_file_name, host_name, &cli.media_dir).await;
+      false
+    },
+    Commands::OutputJsonFeed { output_file_name, host_name } => {
+      commands::output_json_feed(&mut db, output_file_name, host_name, &cli.media_dir).await;
+      false
+    },
+    Commands::OutputHtml { output_dir, host_name, templates, per_page } => {
+      commands::output_html(&mut db, output_dir, host_name, &cli.media_dir, templates, per_page).await;
+      false
+    },
+    // handled before the db is loaded
+    Commands::Migrate { .. } => false
+  };
+  if modified {
+    match storage.save(&db) {
+      Ok(()) => {
+        log::info!("Db sucessfully saved!");
       },
-      _ => {
-        
+      Err(error) => {
+        log::error!("❌ {}", error);
       }
     }
+    if let Err(error) = config_file.sync_feeds(&db) {
+      log::error!("❌ {}", error);
+    }
+  }
+  if failed {
+    std::process::exit(1);
   }
 
-  println!("{:#?}", args);
-  
   /* 
   let feeds = ["https://marmadilemanteater.dev/blog/rss.xml", "https://gamemaking.social/@emma.rss", "https://programming.dev/feeds/u/emma.xml?sort=New", "https://opengameart.org/users/105608/art.xml", "https://itch.io/games/newest/by-marmadilemanteater.xml"];
   let mut rss = Vec::<Rss>::new();
@@ -0,0 +1,191 @@
+use std::path::Path;
+use serde_json::Value;
+
+use crate::dates;
+
+/// The schema version of the `Db` written by this build; bump it whenever the
+/// format changes in a way serde defaults can't paper over and add a step to `MIGRATIONS`
+pub const CURRENT_SCHEMA_VERSION: u64 = 3;
+
+#[derive(Debug)]
+pub enum MigrationError {
+  NotAnObject,
+  TooNew(u64),
+  BackupError(std::io::Error)
+}
+
+impl std::fmt::Display for MigrationError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        MigrationError::NotAnObject => write!(f, "Error upgrading db: expected a JSON object"),
+        MigrationError::TooNew(version) => write!(f, "Error upgrading db: it was written by a newer version of syndication_junction (schema version {}, this version only understands up to {})", version, CURRENT_SCHEMA_VERSION),
+        MigrationError::BackupError(error) => write!(f, "Error copying the db before upgrading it: {}", error)
+     }
+  }
+}
+
+// MIGRATIONS[n] upgrades a db from schema version n to n + 1
+const MIGRATIONS: &[fn(&mut Value)] = &[
+  // 0 -> 1: the settings and the per feed options moved into config.toml. They are
+  // still read out of the db when the config file is first created, so they are
+  // left where they are and only the version is new
+  |_| {},
+  // 1 -> 2: `retain_all_entries` became `retention`
+  |db| {
+    if let Some(feeds) = db.get_mut("rss").and_then(Value::as_object_mut) {
+      for feed in feeds.values_mut().filter_map(Value::as_object_mut) {
+        if let Some(retain_all_entries) = feed.remove("retain_all_entries") {
+          feed.insert(String::from("retention"), Value::from(retention_from_retain_all_entries(retain_all_entries.as_bool())));
+        }
+      }
+    }
+  },
+  // 2 -> 3: items got a normalized `timestamp`, which is usually filled in when they're fetched
+  |db| {
+    let feeds = db.get_mut("rss").and_then(Value::as_object_mut);
+    for feed in feeds.into_iter().flat_map(|feeds| feeds.values_mut()) {
+      let items = feed.pointer_mut("/rss/channel/item").and_then(Value::as_array_mut);
+      for item in items.into_iter().flat_map(|items| items.iter_mut()) {
+        let timestamp = ["pubDate", "updateDate", "createDate"]
+          .into_iter()
+          .filter_map(|field| item.get(field).and_then(Value::as_str))
+          .find_map(dates::parse_timestamp);
+        if let (Some(timestamp), Some(item)) = (timestamp, item.as_object_mut()) {
+          item.insert(String::from("timestamp"), Value::from(timestamp));
+        }
+      }
+    }
+  }
+];
+
+/// What `retain_all_entries` turned into. It was never acted on, so whatever it was set
+/// to everything was kept, and that stays the case; dropping items has to be opted into
+pub fn retention_from_retain_all_entries(retain_all_entries: Option<bool>) -> &'static str {
+  if retain_all_entries == Some(false) {
+    log::warn!("⚠️ `retain_all_entries = false` never dropped any items, so it became `retention = \"keep_all\"`; set `retention = \"mirror_upstream\"` to only keep the items still in the feed");
+  }
+  "keep_all"
+}
+
+/// The schema version a db was written with; dbs from before versioning are version 0
+pub fn schema_version(db: &Value) -> u64 {
+  db.get("schema_version").and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// Upgrades a db as loaded from storage to `CURRENT_SCHEMA_VERSION`, one version at a time
+pub fn upgrade(mut db: Value) -> Result<Value, MigrationError> {
+  if !db.is_object() {
+    return Err(MigrationError::NotAnObject);
+  }
+  let from = schema_version(&db);
+  if from > CURRENT_SCHEMA_VERSION {
+    // saving it would drop whatever the newer version added
+    return Err(MigrationError::TooNew(from));
+  }
+  for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+    migration(&mut db);
+    db["schema_version"] = Value::from(version as u64 + 1);
+    log::info!("✅ Upgraded the db from schema version {} to {}", version, version + 1);
+  }
+  Ok(db)
+}
+
+/// Keeps a copy of a db that is about to be upgraded as `<db>.v<version>`, so the
+/// original is still around if the upgrade goes wrong
+pub fn keep_copy(path: &str, version: u64) -> Result<(), MigrationError> {
+  let copy_path = format!("{path}.v{version}");
+  if Path::new(&copy_path).exists() {
+    return Ok(());
+  }
+  std::fs::copy(path, &copy_path).map_err(MigrationError::BackupError)?;
+  log::info!("✅ Kept a copy of the db from before the upgrade as {copy_path}");
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn version_0_db() -> Value {
+    json!({
+      "title": "Emma",
+      "rss": {
+        "https://a.example/feed": {
+          "retain_all_entries": false,
+          "rss": { "channel": { "title": "A", "link": "https://a.example/", "item": [
+            { "guid": "1", "pubDate": "Wed, 16 Aug 2023 18:00:00 GMT" },
+            { "guid": "2", "updateDate": "2023-08-16T18:00:00Z" },
+            { "guid": "3", "pubDate": "not a date" }
+          ] } }
+        },
+        "https://b.example/feed": {
+          "retain_all_entries": true,
+          "rss": { "channel": { "title": "B", "link": "https://b.example/", "item": [] } }
+        }
+      }
+    })
+  }
+
+  #[test]
+  fn upgrades_to_the_current_version() {
+    let db = upgrade(version_0_db()).unwrap();
+    assert_eq!(schema_version(&db), CURRENT_SCHEMA_VERSION);
+    // the settings are left for the config file to pick up
+    assert_eq!(db["title"], "Emma");
+  }
+
+  #[test]
+  fn retain_all_entries_becomes_keep_all_either_way() {
+    let db = upgrade(version_0_db()).unwrap();
+    for feed in ["https://a.example/feed", "https://b.example/feed"] {
+      assert_eq!(db["rss"][feed]["retention"], "keep_all");
+      assert!(db["rss"][feed].get("retain_all_entries").is_none());
+    }
+  }
+
+  #[test]
+  fn fills_in_timestamps() {
+    let db = upgrade(version_0_db()).unwrap();
+    let items = &db["rss"]["https://a.example/feed"]["rss"]["channel"]["item"];
+    assert_eq!(items[0]["timestamp"], 1692208800);
+    // falls back on the other dates
+    assert_eq!(items[1]["timestamp"], 1692208800);
+    assert!(items[2].get("timestamp").is_none());
+  }
+
+  #[test]
+  fn only_runs_the_steps_after_the_db_version() {
+    let mut db = version_0_db();
+    db["schema_version"] = json!(2);
+    let db = upgrade(db).unwrap();
+    // the 1 -> 2 step was skipped, so the old option is still there
+    assert_eq!(db["rss"]["https://a.example/feed"]["retain_all_entries"], false);
+    assert_eq!(db["rss"]["https://a.example/feed"]["rss"]["channel"]["item"][0]["timestamp"], 1692208800);
+    assert_eq!(schema_version(&db), CURRENT_SCHEMA_VERSION);
+  }
+
+  #[test]
+  fn leaves_current_dbs_alone() {
+    let db = json!({ "schema_version": CURRENT_SCHEMA_VERSION, "rss": {} });
+    assert_eq!(upgrade(db.clone()).unwrap(), db);
+  }
+
+  #[test]
+  fn refuses_newer_dbs() {
+    let db = json!({ "schema_version": CURRENT_SCHEMA_VERSION + 1, "rss": {} });
+    assert!(matches!(upgrade(db), Err(MigrationError::TooNew(version)) if version == CURRENT_SCHEMA_VERSION + 1));
+  }
+
+  #[test]
+  fn refuses_what_isnt_an_object() {
+    assert!(matches!(upgrade(json!([])), Err(MigrationError::NotAnObject)));
+  }
+
+  #[test]
+  fn upgraded_dbs_deserialize() {
+    let db = serde_json::from_value::<crate::structs::Db>(upgrade(version_0_db()).unwrap()).unwrap();
+    assert_eq!(db.rss["https://a.example/feed"].rss.channel.item.len(), 3);
+    assert_eq!(db.settings.title, "Emma");
+  }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::structs::*;
+
+/// How long the items of a feed are kept around once they've been fetched
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum RetentionPolicy {
+  // every item ever fetched is kept
+  #[default]
+  KeepAll,
+  // only the newest n items are kept
+  KeepLast(usize),
+  // only items published within the duration are kept
+  KeepNewerThan(Duration),
+  // only the items still in the upstream feed are kept
+  MirrorUpstream
+}
+
+#[derive(Debug)]
+pub struct RetentionParseError(String);
+
+impl std::fmt::Display for RetentionParseError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "Invalid retention policy `{}`; expected `keep_all`, `keep_last <COUNT>`, `keep_newer_than <DURATION>` or `mirror_upstream`", self.0)
+  }
+}
+
+impl std::error::Error for RetentionParseError {}
+
+impl std::str::FromStr for RetentionPolicy {
+  type Err = RetentionParseError;
+  fn from_str(policy: &str) -> Result<Self, Self::Err> {
+    let error = || RetentionParseError(policy.to_string());
+    let (name, argument) = match policy.trim().split_once(' ') {
+      Some((name, argument)) => (name, argument.trim()),
+      None => (policy.trim(), "")
+    };
+    match (name, argument) {
+      ("keep_all", "") => Ok(RetentionPolicy::KeepAll),
+      ("keep_last", count) => count.parse::<usize>().map(RetentionPolicy::KeepLast).map_err(|_| error()),
+      ("keep_newer_than", duration) => humantime::parse_duration(duration).map(RetentionPolicy::KeepNewerThan).map_err(|_| error()),
+      ("mirror_upstream", "") => Ok(RetentionPolicy::MirrorUpstream),
+      _ => Err(error())
+    }
+  }
+}
+
+impl std::fmt::Display for RetentionPolicy {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      RetentionPolicy::KeepAll => write!(f, "keep_all"),
+      RetentionPolicy::KeepLast(count) => write!(f, "keep_last {}", count),
+      RetentionPolicy::KeepNewerThan(duration) => write!(f, "keep_newer_than {}", humantime::format_duration(*duration)),
+      RetentionPolicy::MirrorUpstream => write!(f, "mirror_upstream")
+    }
+  }
+}
+
+impl TryFrom<String> for RetentionPolicy {
+  type Error = RetentionParseError;
+  fn try_from(policy: String) -> Result<Self, Self::Error> {
+    policy.parse()
+  }
+}
+
+impl From<RetentionPolicy> for String {
+  fn from(policy: RetentionPolicy) -> Self {
+    policy.to_string()
+  }
+}
+
+impl RetentionPolicy {
+  /// The positions of the items in `items` the policy drops, given the guids in the
+  /// last fetched copy of the feed and the current time as a unix timestamp
+  pub fn items_to_prune(&self, items: &[Item], upstream_guids: &[String], now: i64) -> Vec<usize> {
+    match self {
+      RetentionPolicy::KeepAll => vec![],
+      RetentionPolicy::KeepLast(count) => {
+        // newest first; items without a readable date count as older than any with one,
+        // and between equals the one fetched later wins
+        let mut positions = (0..items.len()).collect::<Vec<_>>();
+        positions.sort_by_key(|position| std::cmp::Reverse((items[*position].timestamp, *position)));
+        let mut pruned = positions.split_off((*count).min(positions.len()));
+        pruned.sort();
+        pruned
+      },
+      RetentionPolicy::KeepNewerThan(duration) => {
+        let cutoff = now - duration.as_secs() as i64;
+        // items without a readable date are kept since there's no telling how old they are
+        (0..items.len())
+          .filter(|position| items[*position].timestamp.is_some_and(|timestamp| timestamp < cutoff))
+          .collect()
+      },
+      RetentionPolicy::MirrorUpstream => {
+        if upstream_guids.is_empty() {
+          // never fetched (or fetched empty), so there is nothing to mirror yet
+          return vec![];
+        }
+        let upstream_guids = upstream_guids.iter().collect::<HashSet<_>>();
+        (0..items.len())
+          .filter(|position| !upstream_guids.contains(&items[*position].guid))
+          .collect()
+      }
+    }
+  }
+}
+
+impl FeedOptions {
+  /// The stored items the feed's retention policy would drop right now
+  pub fn items_to_prune(&self, now: i64) -> Vec<&Item> {
+    self.settings.retention
+      .items_to_prune(&self.rss.channel.item, &self.upstream_guids, now)
+      .into_iter()
+      .map(|position| &self.rss.channel.item[position])
+      .collect()
+  }
+  /// Drops the stored items the feed's retention policy doesn't keep and returns them
+  pub fn apply_retention(&mut self, now: i64) -> Vec<Item> {
+    let pruned = self.settings.retention.items_to_prune(&self.rss.channel.item, &self.upstream_guids, now);
+    let removed = self.rss.channel.item.remove_positions(&pruned);
+    for item in removed.iter() {
+      self.tombstones.remove(&item.guid);
+    }
+    removed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helpers::*;
+
+  #[test]
+  fn parses_and_displays_policies() {
+    assert_eq!("keep_all".parse::<RetentionPolicy>().unwrap(), RetentionPolicy::KeepAll);
+    assert_eq!(" keep_last  5 ".parse::<RetentionPolicy>().unwrap(), RetentionPolicy::KeepLast(5));
+    assert_eq!("keep_newer_than 30days".parse::<RetentionPolicy>().unwrap(), RetentionPolicy::KeepNewerThan(Duration::from_secs(30 * 24 * 60 * 60)));
+    assert_eq!("mirror_upstream".parse::<RetentionPolicy>().unwrap(), RetentionPolicy::MirrorUpstream);
+    for policy in ["keep_all", "keep_last 5", "keep_newer_than 30days", "mirror_upstream"] {
+      assert_eq!(policy.parse::<RetentionPolicy>().unwrap().to_string(), policy);
+    }
+  }
+
+  #[test]
+  fn rejects_invalid_policies() {
+    for policy in ["", "keep_last", "keep_last:5", "keep_last -1", "keep_all 5", "keep_newer_than soon", "keep_some"] {
+      assert!(policy.parse::<RetentionPolicy>().is_err(), "{policy}");
+    }
+  }
+
+  #[test]
+  fn keep_all_prunes_nothing() {
+    let items = vec![item("a", Some("Wed, 16 Aug 2023 18:00:00 +0000")), item("b", None)];
+    assert!(RetentionPolicy::KeepAll.items_to_prune(&items, &[], i64::MAX).is_empty());
+  }
+
+  #[test]
+  fn keep_last_keeps_the_newest() {
+    let items = vec![
+      item("middle", Some("Wed, 16 Aug 2023 18:00:00 +0000")),
+      item("newest", Some("Thu, 17 Aug 2023 18:00:00 +0000")),
+      item("oldest", Some("Tue, 15 Aug 2023 18:00:00 +0000"))
+    ];
+    assert_eq!(RetentionPolicy::KeepLast(2).items_to_prune(&items, &[], 0), vec![2]);
+    assert_eq!(RetentionPolicy::KeepLast(0).items_to_prune(&items, &[], 0), vec![0, 1, 2]);
+    assert!(RetentionPolicy::KeepLast(5).items_to_prune(&items, &[], 0).is_empty());
+  }
+
+  #[test]
+  fn keep_last_ties_go_to_the_item_fetched_later() {
+    let date = Some("Wed, 16 Aug 2023 18:00:00 +0000");
+    let items = vec![item("first", date), item("second", date), item("third", date)];
+    assert_eq!(RetentionPolicy::KeepLast(1).items_to_prune(&items, &[], 0), vec![0, 1]);
+    assert_eq!(RetentionPolicy::KeepLast(2).items_to_prune(&items, &[], 0), vec![0]);
+  }
+
+  #[test]
+  fn keep_last_counts_undated_items_as_oldest() {
+    let items = vec![item("undated", None), item("dated", Some("Wed, 16 Aug 2023 18:00:00 +0000")), item("unreadable", Some("soon"))];
+    assert_eq!(RetentionPolicy::KeepLast(1).items_to_prune(&items, &[], 0), vec![0, 2]);
+    // between two undated items the one fetched later wins, like with any other tie
+    assert_eq!(RetentionPolicy::KeepLast(2).items_to_prune(&items, &[], 0), vec![0]);
+  }
+
+  #[test]
+  fn keep_last_goes_by_the_stored_timestamp() {
+    // which is the publish date, or the update date for items without one
+    let mut edited = item("edited", Some("Tue, 15 Aug 2023 18:00:00 +0000"));
+    edited.update_date = Some(String::from("Fri, 18 Aug 2023 18:00:00 +0000"));
+    let updated = item_with(serde_json::json!({ "guid": "updated", "updateDate": "Thu, 17 Aug 2023 18:00:00 +0000" }));
+    assert_eq!(RetentionPolicy::KeepLast(1).items_to_prune(&[edited, updated], &[], 0), vec![0]);
+  }
+
+  #[test]
+  fn keep_newer_than_keeps_recent_and_undated_items() {
+    // 2023-08-16T18:00:00Z
+    let now = 1692208800;
+    let items = vec![
+      item("hour_old", Some("Wed, 16 Aug 2023 17:00:00 +0000")),
+      item("two_days_old", Some("Mon, 14 Aug 2023 18:00:00 +0000")),
+      item("undated", None)
+    ];
+    assert_eq!(RetentionPolicy::KeepNewerThan(Duration::from_secs(24 * 60 * 60)).items_to_prune(&items, &[], now), vec![1]);
+    assert!(RetentionPolicy::KeepNewerThan(Duration::from_secs(3 * 24 * 60 * 60)).items_to_prune(&items, &[], now).is_empty());
+  }
+
+  #[test]
+  fn mirror_upstream_keeps_what_is_still_in_the_feed() {
+    let items = vec![item("gone", None), item("still_there", None)];
+    assert_eq!(RetentionPolicy::MirrorUpstream.items_to_prune(&items, &[String::from("still_there")], 0), vec![0]);
+    // before the first fetch there is nothing to mirror
+    assert!(RetentionPolicy::MirrorUpstream.items_to_prune(&items, &[], 0).is_empty());
+  }
+
+  #[test]
+  fn apply_retention_removes_items_and_their_tombstones() {
+    let mut options = FeedOptions::default();
+    options.settings.retention = RetentionPolicy::KeepLast(1);
+    options.rss.channel.item.extend([
+      item("old", Some("Tue, 15 Aug 2023 18:00:00 +0000")),
+      item("new", Some("Wed, 16 Aug 2023 18:00:00 +0000"))
+    ]);
+    options.tombstones.insert(String::from("old"), String::from("Wed, 16 Aug 2023 18:00:00 +0000"));
+    assert_eq!(guids(&options.items_to_prune(0).into_iter().cloned().collect::<Vec<_>>()), vec!["old"]);
+    let removed = options.apply_retention(0);
+    assert_eq!(guids(&removed), vec!["old"]);
+    assert_eq!(guids(&options.rss.channel.item), vec!["new"]);
+    assert!(options.rss.channel.item.get_by_guid("old").is_none());
+    assert!(options.tombstones.is_empty());
+  }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::path::Path;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::{Value, json};
+
+use crate::migrations::{self, MigrationError};
+use crate::storage::{Storage, StorageError};
+use crate::structs::*;
+
+#[derive(Debug)]
+pub enum SqliteError {
+  NotFound(String),
+  Sqlite(rusqlite::Error),
+  Json(serde_json::error::Error),
+  Migration(MigrationError)
+}
+
+impl SqliteError {
+  pub fn is_not_found(&self) -> bool {
+    matches!(self, SqliteError::NotFound(_))
+  }
+}
+
+impl std::fmt::Display for SqliteError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        SqliteError::NotFound(path) => write!(f, "Error opening database: {} does not exist", path),
+        SqliteError::Sqlite(error) => write!(f, "Error accessing database: {}", error),
+        SqliteError::Json(error) => write!(f, "Error (de)serializing JSON in database: {}", error),
+        SqliteError::Migration(error) => write!(f, "{}", error)
+     }
+  }
+}
+
+impl From<rusqlite::Error> for SqliteError {
+  fn from(error: rusqlite::Error) -> Self {
+    SqliteError::Sqlite(error)
+  }
+}
+
+impl From<serde_json::error::Error> for SqliteError {
+  fn from(error: serde_json::error::Error) -> Self {
+    SqliteError::Json(error)
+  }
+}
+
+impl From<MigrationError> for SqliteError {
+  fn from(error: MigrationError) -> Self {
+    SqliteError::Migration(error)
+  }
+}
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS metadata (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS feeds (
+  url TEXT PRIMARY KEY,
+  data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS items (
+  feed_url TEXT NOT NULL REFERENCES feeds (url) ON DELETE CASCADE,
+  guid TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  title TEXT,
+  link TEXT,
+  pub_date TEXT,
+  data TEXT NOT NULL,
+  PRIMARY KEY (feed_url, guid)
+);
+CREATE INDEX IF NOT EXISTS items_by_position ON items (feed_url, position);
+CREATE TABLE IF NOT EXISTS media (
+  feed_url TEXT NOT NULL,
+  item_guid TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  url TEXT NOT NULL,
+  data TEXT NOT NULL,
+  PRIMARY KEY (feed_url, item_guid, position),
+  FOREIGN KEY (feed_url, item_guid) REFERENCES items (feed_url, guid) ON DELETE CASCADE
+);
+"#;
+
+/// Stores the `Db` in an embedded SQLite database, with feeds, items and
+/// media each kept as rows of their own table.
+///
+/// Columns that are useful to query (guid, title, link, ...) are pulled out of
+/// each row, but the row's `data` column always holds the full JSON of the
+/// value minus its children, so nothing is lost when new fields are added.
+/// Saving only writes the rows that changed since the db was loaded.
+pub struct SqliteStorage {
+  pub path: String
+}
+
+// the item as stored in its row: its JSON with the media left out (an empty list keeps
+// `Some` apart from `None`), and the JSON of each of its media
+fn get_item_rows(item: &Item) -> Result<(String, Vec<String>), serde_json::error::Error> {
+  let mut data = serde_json::to_value(item)?;
+  if item.media_content.is_some() {
+    data["media:content"] = json!([]);
+  }
+  let media = item.media_content.iter()
+    .flatten()
+    .map(serde_json::to_string)
+    .collect::<Result<Vec<_>, _>>()?;
+  Ok((data.to_string(), media))
+}
+
+/// Puts a feed's rows back together into the same JSON as in db.json
+fn read_feed(connection: &Connection, url: &str, data: &str) -> Result<Value, SqliteError> {
+  let mut feed = serde_json::from_str::<Value>(data)?;
+  let mut media_statement = connection.prepare_cached("SELECT item_guid, data FROM media WHERE feed_url = ?1 ORDER BY item_guid, position")?;
+  let mut media = HashMap::<String, Vec<Value>>::new();
+  for row in media_statement.query_map([url], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))? {
+    let (guid, data) = row?;
+    media.entry(guid).or_default().push(serde_json::from_str::<Value>(&data)?);
+  }
+  let mut items_statement = connection.prepare_cached("SELECT guid, data FROM items WHERE feed_url = ?1 ORDER BY position")?;
+  let mut items = Vec::new();
+  for row in items_statement.query_map([url], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))? {
+    let (guid, data) = row?;
+    let mut item = serde_json::from_str::<Value>(&data)?;
+    if let Some(media_content) = item.get_mut("media:content").and_then(Value::as_array_mut) {
+      media_content.extend(media.remove(&guid).unwrap_or_default());
+    }
+    items.push(item);
+  }
+  feed["rss"]["channel"]["item"] = Value::Array(items);
+  Ok(feed)
+}
+
+/// Writes the rows of a feed that differ from the stored ones and deletes the items that were pruned
+fn write_feed(connection: &Connection, url: &str, options: &FeedOptions) -> Result<(), SqliteError> {
+  let mut feed = serde_json::to_value(options)?;
+  feed["rss"]["channel"]["item"] = json!([]);
+  connection
+    .prepare_cached("INSERT INTO feeds (url, data) VALUES (?1, ?2) ON CONFLICT (url) DO UPDATE SET data = excluded.data WHERE data != excluded.data")?
+    .execute(params![url, feed.to_string()])?;
+  let mut stored_items = HashMap::<String, (i64, String)>::new();
+  for row in connection
+    .prepare_cached("SELECT guid, position, data FROM items WHERE feed_url = ?1")?
+    .query_map([url], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?)))? {
+    let (guid, position, data) = row?;
+    stored_items.insert(guid, (position, data));
+  }
+  let mut stored_media = HashMap::<String, Vec<String>>::new();
+  for row in connection
+    .prepare_cached("SELECT item_guid, data FROM media WHERE feed_url = ?1 ORDER BY item_guid, position")?
+    .query_map([url], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))? {
+    let (guid, data) = row?;
+    stored_media.entry(guid).or_default().push(data);
+  }
+  let mut update_position = connection.prepare_cached("UPDATE items SET position = ?3 WHERE feed_url = ?1 AND guid = ?2")?;
+  let mut upsert_item = connection.prepare_cached("INSERT INTO items (feed_url, guid, position, title, link, pub_date, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+    ON CONFLICT (feed_url, guid) DO UPDATE SET position = excluded.position, title = excluded.title, link = excluded.link, pub_date = excluded.pub_date, data = excluded.data")?;
+  let mut delete_media = connection.prepare_cached("DELETE FROM media WHERE feed_url = ?1 AND item_guid = ?2")?;
+  let mut insert_media = connection.prepare_cached("INSERT INTO media (feed_url, item_guid, position, url, data) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+  for (position, item) in options.rss.channel.item.iter().enumerate() {
+    let position = position as i64;
+    let (data, media) = get_item_rows(item)?;
+    let media_unchanged = stored_media.get(&item.guid).map(Vec::as_slice).unwrap_or_default() == media.as_slice();
+    match stored_items.remove(&item.guid) {
+      Some((stored_position, stored_data)) if stored_data == data && media_unchanged => {
+        // earlier items were pruned, so only its place in the list moved
+        if stored_position != position {
+          update_position.execute(params![url, item.guid, position])?;
+        }
+      },
+      _ => {
+        upsert_item.execute(params![url, item.guid, position, item.title, item.link, item.pub_date, data])?;
+        if !media_unchanged {
+          delete_media.execute(params![url, item.guid])?;
+          for (media_position, (media_data, media)) in media.iter().zip(item.media_content.iter().flatten()).enumerate() {
+            insert_media.execute(params![url, item.guid, media_position as i64, media.url, media_data])?;
+          }
+        }
+      }
+    }
+  }
+  // whatever is left was pruned; its media goes with it
+  let mut delete_item = connection.prepare_cached("DELETE FROM items WHERE feed_url = ?1 AND guid = ?2")?;
+  for guid in stored_items.keys() {
+    delete_item.execute(params![url, guid])?;
+  }
+  Ok(())
+}
+
+impl SqliteStorage {
+  fn open(&self) -> Result<Connection, SqliteError> {
+    let connection = Connection::open(&self.path)?;
+    connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+  }
+  fn load_db(&self) -> Result<Db, SqliteError> {
+    if !Path::new(&self.path).exists() {
+      return Err(SqliteError::NotFound(self.path.clone()));
+    }
+    let connection = self.open()?;
+    let metadata = connection
+      .query_row("SELECT data FROM metadata WHERE id = 1", [], |row| row.get::<_, String>(0))
+      .optional()?;
+    let mut metadata = match metadata {
+      Some(metadata) => serde_json::from_str::<Value>(&metadata)?,
+      None => json!({ "schema_version": migrations::CURRENT_SCHEMA_VERSION })
+    };
+    let mut feeds_statement = connection.prepare("SELECT url, data FROM feeds ORDER BY url")?;
+    let feeds = feeds_statement
+      .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+      .collect::<Result<Vec<_>, _>>()?;
+    let version = migrations::schema_version(&metadata);
+    if version != migrations::CURRENT_SCHEMA_VERSION {
+      // older dbs are put back together into one JSON value so they go through the same upgrades as db.json
+      let mut rss = serde_json::Map::new();
+      for (url, data) in feeds {
+        let feed = read_feed(&connection, &url, &data)?;
+        rss.insert(url, feed);
+      }
+      metadata["rss"] = Value::Object(rss);
+      if version < migrations::CURRENT_SCHEMA_VERSION {
+        migrations::keep_copy(&self.path, version)?;
+      }
+      let db = migrations::upgrade(metadata)?;
+      return Ok(serde_json::from_value::<Db>(db)?);
+    }
+    metadata["rss"] = json!({});
+    let mut db = serde_json::from_value::<Db>(metadata)?;
+    for (url, data) in feeds {
+      let feed = read_feed(&connection, &url, &data)?;
+      db.rss.insert(url, serde_json::from_value::<FeedOptions>(feed)?);
+    }
+    Ok(db)
+  }
+  fn save_db(&self, db: &Db) -> Result<(), SqliteError> {
+    let mut connection = self.open()?;
+    let transaction = connection.transaction()?;
+    // everything in the db but its feeds, which is only the schema version now that the settings live in the config file
+    let mut metadata = serde_json::to_value(db)?;
+    metadata["rss"] = json!({});
+    transaction.execute(
+      "INSERT INTO metadata (id, data) VALUES (1, ?1) ON CONFLICT (id) DO UPDATE SET data = excluded.data WHERE data != excluded.data",
+      [metadata.to_string()]
+    )?;
+    let stored_urls = transaction
+      .prepare("SELECT url FROM feeds")?
+      .query_map([], |row| row.get::<_, String>(0))?
+      .collect::<Result<Vec<_>, _>>()?;
+    // feeds that were removed go along with their items and media
+    for url in stored_urls.iter().filter(|url| !db.rss.contains_key(*url)) {
+      transaction.execute("DELETE FROM feeds WHERE url = ?1", [url])?;
+    }
+    for (url, options) in db.rss.iter() {
+      write_feed(&transaction, url, options)?;
+    }
+    transaction.commit()?;
+    Ok(())
+  }
+}
+
+impl Storage for SqliteStorage {
+  fn path(&self) -> &str {
+    &self.path
+  }
+  fn load(&self) -> Result<Db, StorageError> {
+    self.load_db().map_err(StorageError::Sqlite)
+  }
+  fn save(&self, db: &Db) -> Result<(), StorageError> {
+    self.save_db(db).map_err(StorageError::Sqlite)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use crate::test_helpers::*;
+
+  const URL: &str = "https://feed.example/rss.xml";
+
+  fn storage(name: &str) -> SqliteStorage {
+    SqliteStorage { path: temp_dir(name).join("db.sqlite").to_string_lossy().to_string() }
+  }
+
+  fn db_with_items(items: Vec<Item>) -> Db {
+    let mut db = Db::new();
+    let options = db.rss.entry(URL.to_string()).or_default();
+    options.rss.channel.title = String::from("Feed");
+    options.rss.channel.item.extend(items);
+    db
+  }
+
+  // every write to the item and media tables from here on, as `table op guid`
+  fn record_writes(storage: &SqliteStorage) -> Connection {
+    let connection = Connection::open(&storage.path).unwrap();
+    connection.execute_batch("
+      CREATE TABLE writes (write TEXT);
+      CREATE TRIGGER items_insert AFTER INSERT ON items BEGIN INSERT INTO writes VALUES ('items insert ' || new.guid); END;
+      CREATE TRIGGER items_update AFTER UPDATE ON items BEGIN INSERT INTO writes VALUES ('items update ' || new.guid); END;
+      CREATE TRIGGER items_delete AFTER DELETE ON items BEGIN INSERT INTO writes VALUES ('items delete ' || old.guid); END;
+      CREATE TRIGGER media_insert AFTER INSERT ON media BEGIN INSERT INTO writes VALUES ('media insert ' || new.item_guid); END;
+      CREATE TRIGGER media_delete AFTER DELETE ON media BEGIN INSERT INTO writes VALUES ('media delete ' || old.item_guid); END;
+      CREATE TRIGGER feeds_update AFTER UPDATE ON feeds BEGIN INSERT INTO writes VALUES ('feeds update ' || new.url); END;
+    ").unwrap();
+    connection
+  }
+
+  fn take_writes(connection: &Connection) -> Vec<String> {
+    let mut writes = connection.prepare("SELECT write FROM writes ORDER BY rowid").unwrap()
+      .query_map([], |row| row.get::<_, String>(0)).unwrap()
+      .collect::<Result<Vec<_>, _>>().unwrap();
+    connection.execute("DELETE FROM writes", []).unwrap();
+    writes.sort();
+    writes
+  }
+
+  #[test]
+  fn round_trips_the_db() {
+    let storage = storage("sqlite-round-trip");
+    let mut db = db_with_items(vec![
+      item_with(json!({
+        "guid": "a",
+        "title": "A",
+        "pubDate": "Wed, 16 Aug 2023 18:00:00 +0000",
+        "media:content": [
+          { "@url": "https://feed.example/1.png", "@type": "image/png", "@medium": "image" },
+          { "@url": "https://feed.example/2.png", "@type": "image/png", "@medium": "image" }
+        ]
+      })),
+      // an empty list of media stays apart from none at all
+      item_with(json!({ "guid": "b", "media:content": [] })),
+      item("c", None)
+    ]);
+    let options = db.rss.get_mut(URL).unwrap();
+    options.etag = Some(String::from("\"1\""));
+    options.tombstones.insert(String::from("d"), String::from("Wed, 16 Aug 2023 18:00:00 +0000"));
+    db.rss.insert(String::from("https://other.example/feed.json"), FeedOptions::default());
+    storage.save_db(&db).unwrap();
+    assert_eq!(storage.load_db().unwrap(), db);
+  }
+
+  #[test]
+  fn only_writes_what_changed() {
+    let storage = storage("sqlite-diff-write");
+    let mut db = db_with_items(vec![
+      item("a", None),
+      item("b", None),
+      item_with(json!({ "guid": "c", "title": "C" })),
+      item_with(json!({ "guid": "d", "media:content": [{ "@url": "https://feed.example/d.png", "@type": "image/png", "@medium": "image" }] }))
+    ]);
+    storage.save_db(&db).unwrap();
+    let connection = record_writes(&storage);
+    storage.save_db(&db).unwrap();
+    assert!(take_writes(&connection).is_empty());
+    // pruning `a` moves everything after it up a place
+    let options = db.rss.get_mut(URL).unwrap();
+    options.rss.channel.item.remove_positions(&[0]);
+    options.rss.channel.item[1].title = Some(String::from("C, edited"));
+    options.rss.channel.item.push(item("e", None));
+    storage.save_db(&db).unwrap();
+    assert_eq!(take_writes(&connection), vec!["items delete a", "items insert e", "items update b", "items update c", "items update d"]);
+    let loaded = storage.load_db().unwrap();
+    assert_eq!(guids(&loaded.rss[URL].rss.channel.item), vec!["b", "c", "d", "e"]);
+    assert_eq!(loaded, db);
+  }
+
+  #[test]
+  fn removes_feeds_with_their_items_and_media() {
+    let storage = storage("sqlite-remove-feed");
+    let mut db = db_with_items(vec![item_with(json!({ "guid": "a", "media:content": [{ "@url": "https://feed.example/a.png", "@type": "image/png", "@medium": "image" }] }))]);
+    storage.save_db(&db).unwrap();
+    db.rss.remove(URL);
+    storage.save_db(&db).unwrap();
+    let connection = Connection::open(&storage.path).unwrap();
+    for table in ["feeds", "items", "media"] {
+      assert_eq!(connection.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get::<_, i64>(0)).unwrap(), 0, "{table}");
+    }
+  }
+
+  #[test]
+  fn reports_a_missing_db() {
+    let storage = storage("sqlite-missing");
+    assert!(storage.load_db().unwrap_err().is_not_found());
+  }
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::{Write, Read};
+use std::path::{Path, PathBuf};
+
+use crate::migrations::{self, MigrationError};
+use crate::sqlite::SqliteError;
+use crate::structs::Db;
+
+#[derive(Debug)]
+pub enum DbCreateError {
+  FileCreateError(std::io::Error),
+  FormattingError(serde_json::error::Error),
+  FileWriteError(std::io::Error),
+  FileSyncError(std::io::Error),
+  BackupError(std::io::Error),
+  FileRenameError(std::io::Error)
+}
+
+impl std::fmt::Display for DbCreateError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        DbCreateError::FileCreateError(error) => write!(f, "Error creating file: {}", error),
+        DbCreateError::FormattingError(error) => write!(f, "Error formatting file: {}", error),
+        DbCreateError::FileWriteError(error) => write!(f, "Error writing to file: {}", error),
+        DbCreateError::FileSyncError(error) => write!(f, "Error flushing file to disk: {}", error),
+        DbCreateError::BackupError(error) => write!(f, "Error backing up file: {}", error),
+        DbCreateError::FileRenameError(error) => write!(f, "Error replacing file: {}", error)
+     }
+  }
+}
+
+fn get_sibling_path(path: &Path, suffix: &str) -> PathBuf {
+  let mut file_name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+  file_name.push(suffix);
+  path.with_file_name(file_name)
+}
+
+/// Shifts `db.json.1` to `db.json.2` and so on, dropping the oldest,
+/// and then copies the current db to `db.json.1`
+fn rotate_backups(path: &Path, backups: usize) -> Result<(), std::io::Error> {
+  if backups == 0 || !path.exists() {
+    return Ok(());
+  }
+  for n in (1..backups).rev() {
+    let from = get_sibling_path(path, &format!(".{}", n));
+    if from.exists() {
+      std::fs::rename(&from, get_sibling_path(path, &format!(".{}", n + 1)))?;
+    }
+  }
+  std::fs::copy(path, get_sibling_path(path, ".1"))?;
+  Ok(())
+}
+
+/// Writes the db to a temporary file next to `path`, flushes it to disk and
+/// then renames it over `path`, so a crash part way through never leaves a
+/// truncated db behind. The previous `backups` versions are kept as `path.1`,
+/// `path.2`, etc.
+pub fn save_db(db: &Db, path: &str, backups: usize) -> Result<(),DbCreateError> {
+  let formatted_db = match serde_json::to_string_pretty(&db) {
+    Ok(db) => db,
+    Err(error) => {
+      return Err(DbCreateError::FormattingError(error))
+    }
+  };
+  let path = Path::new(path);
+  let temp_path = get_sibling_path(path, ".tmp");
+  let mut file = match File::create(&temp_path) {
+    Ok(result) => result,
+    Err(error) => {
+      return Err(DbCreateError::FileCreateError(error));
+    }
+  };
+  if let Err(error) = write!(file, "{}", formatted_db) {
+    let _ = std::fs::remove_file(&temp_path);
+    return Err(DbCreateError::FileWriteError(error));
+  }
+  if let Err(error) = file.sync_all() {
+    let _ = std::fs::remove_file(&temp_path);
+    return Err(DbCreateError::FileSyncError(error));
+  }
+  drop(file);
+  rotate_backups(path, backups).map_err(DbCreateError::BackupError)?;
+  std::fs::rename(&temp_path, path).map_err(DbCreateError::FileRenameError)?;
+  // make the rename itself durable; directories can't be opened like this on windows
+  #[cfg(unix)]
+  if let Some(parent) = path.parent() {
+    let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+    File::open(parent)
+      .and_then(|dir| dir.sync_all())
+      .map_err(DbCreateError::FileSyncError)?;
+  }
+  Ok(())
+}
+
+#[derive(Debug)]
+pub enum GetDbError {
+  FileOpenError(std::io::Error),
+  FileReadError(std::io::Error),
+  JsonDeserializeError(serde_json::error::Error),
+  MigrationError(MigrationError)
+}
+
+impl GetDbError {
+  /// Whether the db just hasn't been created yet, as opposed to existing but being unreadable
+  pub fn is_not_found(&self) -> bool {
+    match self {
+      GetDbError::FileOpenError(error) => error.kind() == std::io::ErrorKind::NotFound,
+      _ => false
+    }
+  }
+}
+
+impl std::fmt::Display for GetDbError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        GetDbError::FileOpenError(error) => write!(f, "Error opening file: {}", error),
+        GetDbError::FileReadError(error) => write!(f, "Error reading file: {}", error),
+        GetDbError::JsonDeserializeError(error) => write!(f, "Error deserializing JSON from file: {}", error),
+        GetDbError::MigrationError(error) => write!(f, "{}", error)
+     }
+  }
+}
+
+pub fn get_db(path: &str) -> Result<Db, GetDbError> {
+  let mut f = match File::open(path) {
+    Ok(f) => f,
+    Err(error) => {
+      return Err(GetDbError::FileOpenError(error))
+    }
+  };
+  let mut output = String::from("");
+  match f.read_to_string(&mut output) {
+    Ok(_) => {
+      let db = serde_json::from_str::<serde_json::Value>(&output).map_err(GetDbError::JsonDeserializeError)?;
+      let version = migrations::schema_version(&db);
+      if version < migrations::CURRENT_SCHEMA_VERSION {
+        migrations::keep_copy(path, version).map_err(GetDbError::MigrationError)?;
+      }
+      let db = migrations::upgrade(db).map_err(GetDbError::MigrationError)?;
+      serde_json::from_value::<Db>(db).map_err(GetDbError::JsonDeserializeError)
+    },
+    Err(error) => {
+      Err(GetDbError::FileReadError(error))
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+  Get(GetDbError),
+  Create(DbCreateError),
+  Sqlite(SqliteError)
+}
+
+impl StorageError {
+  /// Whether the store just hasn't been created yet, as opposed to existing but being unreadable
+  pub fn is_not_found(&self) -> bool {
+    match self {
+      StorageError::Get(error) => error.is_not_found(),
+      StorageError::Sqlite(error) => error.is_not_found(),
+      _ => false
+    }
+  }
+}
+
+impl std::fmt::Display for StorageError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+     match self {
+        StorageError::Get(error) => write!(f, "{}", error),
+        StorageError::Create(error) => write!(f, "{}", error),
+        StorageError::Sqlite(error) => write!(f, "{}", error)
+     }
+  }
+}
+
+/// Somewhere the whole `Db` can be loaded from and saved back to
+pub trait Storage {
+  /// The file backing the store, used to name the lock file and in messages
+  fn path(&self) -> &str;
+  fn load(&self) -> Result<Db, StorageError>;
+  fn save(&self, db: &Db) -> Result<(), StorageError>;
+}
+
+/// The original storage: the whole `Db` as one pretty printed JSON file
+pub struct JsonStorage {
+  pub path: String
+}
+
+impl Storage for JsonStorage {
+  fn path(&self) -> &str {
+    &self.path
+  }
+  fn load(&self) -> Result<Db, StorageError> {
+    get_db(&self.path).map_err(StorageError::Get)
+  }
+  fn save(&self, db: &Db) -> Result<(), StorageError> {
+    save_db(db, &self.path, db.settings.db_backups).map_err(StorageError::Create)
+  }
+}
@@ -1,8 +1,12 @@
 use std::{collections::HashMap, cmp::Ordering};
 
-use chrono::{NaiveDateTime, TimeZone};
 use serde::{Deserialize, Serialize};
 
+use crate::dates;
+use crate::dedup::{self, DedupStrategy, DedupWinner};
+use crate::helpers::slugify;
+use crate::retention::RetentionPolicy;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct MediaContent {
@@ -19,6 +23,13 @@ pub struct MediaContent {
 }
 
 impl MediaContent {
+  pub fn new(url: String, mime_type: String, file_size: Option<String>, description: Option<String>) -> MediaContent {
+    let medium = match mime_type.split('/').next() {
+      Some(medium @ ("image" | "video" | "audio")) => medium.to_string(),
+      _ => String::from("document")
+    };
+    MediaContent { url, description, mime_type, file_size, medium }
+  }
   pub fn into_html(&self) -> String {
     let url = &self.url;
     let description = &self.description.clone().unwrap_or(String::from(""));
@@ -28,12 +39,13 @@ impl MediaContent {
       let r#type = &self.mime_type;
       format!("<video src=\"{url}\" type=\"{type}\" controls>{description}</video>")
     } else {
-      format!("{description}")
+      description.to_string()
     }
   }
 }
 
 pub trait ContainsMedia {
+  #[allow(clippy::wrong_self_convention)]
   fn into_html(&self) -> Vec::<String>;
 }
 
@@ -41,7 +53,7 @@ impl ContainsMedia for Option<Vec<MediaContent>> {
   fn into_html(&self) -> Vec::<String> {
     match &self {
       Some(media_content) => {
-        media_content.into_iter().map(|c| c.into_html()).collect::<Vec::<String>>()
+        media_content.iter().map(|c| c.into_html()).collect::<Vec::<String>>()
       },
       None => {
         vec![]
@@ -66,32 +78,171 @@ pub struct Item {
   #[serde(alias = "media:content", rename(serialize = "media:content", deserialize = "media-content"))]
   pub media_content: Option<Vec<MediaContent>>,
   #[serde(alias = "content:encoded", rename(serialize = "content:encoded", deserialize = "content-encoded"))]
-  pub content_encoded: Option<String>
+  pub content_encoded: Option<String>,
+  // earlier versions of the item, oldest first; never part of the output
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub revisions: Vec<ItemRevision>,
+  // when the item was published as a UTC unix timestamp, filled in from whichever date
+  // format the feed used when the item is fetched; never part of the output
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub timestamp: Option<i64>
 }
 
-fn get_timestamp_from_string(string: Option<String>, fmt_string: &str) -> Option<i64> {
-  string
-    .map(|d| {
-        Some(NaiveDateTime::parse_from_str(&d, fmt_string)
-              .map(|dt| Some(dt))
-              .unwrap_or(None)
-              .map(|dt| dt.timestamp()))
-    })
-    .unwrap_or(None)
-    .unwrap_or(None)
+/// What an item looked like before a fetch changed it
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ItemRevision {
+  // when the fetch that replaced this version happened
+  pub fetched_at: String,
+  pub changes: Vec<FieldChange>
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct FieldChange {
+  pub field: String,
+  pub old: Option<String>,
+  pub new: Option<String>
+}
+
+impl FieldChange {
+  /// Whether the change is to what the item says, as opposed to just its dates
+  pub fn is_material(&self) -> bool {
+    matches!(self.field.as_str(), "title" | "description" | "content:encoded" | "media:content")
+  }
+}
+
+fn diff_field(changes: &mut Vec<FieldChange>, field: &str, old: &Option<String>, new: &Option<String>) {
+  if old != new {
+    changes.push(FieldChange { field: field.to_string(), old: old.clone(), new: new.clone() });
+  }
+}
+
+fn get_media_urls(media_content: &Option<Vec<MediaContent>>) -> Option<String> {
+  media_content.as_ref().map(|media_content| media_content.iter().map(|media| media.url.as_str()).collect::<Vec<_>>().join(" "))
+}
+
+fn get_timestamp_from_string(string: Option<&String>) -> Option<i64> {
+  string.and_then(|d| dates::parse_timestamp(d))
 }
 
 impl Item {
   pub fn get_created_timestamp(&self) -> Option<i64> {
-    get_timestamp_from_string(self.create_date.as_ref().map(|d| d.replace("GMT", "+0000")), "%a, %d %h %Y %H:%M:%S %z")
+    get_timestamp_from_string(self.create_date.as_ref())
   }
   pub fn get_updated_timestamp(&self) -> Option<i64> {
-    get_timestamp_from_string(self.update_date.as_ref().map(|d| d.replace("GMT", "+0000")), "%a, %d %h %Y %H:%M:%S %z")
+    get_timestamp_from_string(self.update_date.as_ref())
   }
   pub fn get_published_timestamp(&self) -> Option<i64> {
-    get_timestamp_from_string(self.pub_date.as_ref().map(|d| d.replace("GMT", "+0000")), "%a, %d %h %Y %H:%M:%S %z")
+    get_timestamp_from_string(self.pub_date.as_ref())
+  }
+  // when the item was published, falling back to when it was updated or created
+  // for feeds that leave the publish date out
+  fn get_normalized_timestamp(&self) -> Option<i64> {
+    self.get_published_timestamp()
+      .or(self.get_updated_timestamp())
+      .or(self.get_created_timestamp())
   }
-  pub fn update(&mut self, new_item: Item) {
+  /// Fills in `timestamp` from the item's dates
+  pub fn normalize_timestamp(&mut self) {
+    self.timestamp = self.get_normalized_timestamp();
+  }
+  // the newest of the item's dates, if any of them can be read
+  fn get_latest_timestamp(&self) -> Option<i64> {
+    [self.get_published_timestamp(), self.get_updated_timestamp(), self.get_created_timestamp()]
+      .into_iter()
+      .flatten()
+      .max()
+  }
+  /// The item as an entry of an Atom feed
+  pub fn into_atom_entry(&self) -> AtomEntry {
+    let published = self.get_published_timestamp().or(self.timestamp).and_then(dates::format_rfc3339);
+    // Atom requires entries to say when they were last updated
+    let updated = self.get_latest_timestamp()
+      .or(self.timestamp)
+      .and_then(dates::format_rfc3339)
+      .unwrap_or(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+    let mut link = vec![];
+    if let Some(href) = &self.link {
+      link.push(Link { rel: String::from("alternate"), href: href.clone(), mime_type: Some(String::from("text/html")), length: None });
+    }
+    for media in self.media_content.iter().flatten() {
+      link.push(Link { rel: String::from("enclosure"), href: media.url.clone(), mime_type: Some(media.mime_type.clone()), length: media.file_size.clone() });
+    }
+    AtomEntry {
+      id: self.guid.clone(),
+      title: self.title.clone().unwrap_or_default(),
+      updated,
+      published,
+      author: self.author.as_ref()
+        .filter(|author| !author.name.is_empty())
+        .map(|author| AtomPerson { name: author.name.clone(), uri: author.uri.clone() }),
+      link,
+      summary: self.description.clone().map(AtomHtml::new),
+      content: self.content_encoded.clone().map(AtomHtml::new)
+    }
+  }
+  /// The item as an item of a JSON Feed
+  pub fn into_json_feed_item(&self) -> JsonFeedItem {
+    let author = self.author.as_ref().filter(|author| !author.name.is_empty()).map(|author| JsonFeedAuthor {
+      name: Some(author.name.clone()),
+      url: Some(author.uri.clone()).filter(|uri| !uri.is_empty()),
+      avatar: None
+    });
+    JsonFeedItem {
+      id: serde_json::Value::String(self.guid.clone()),
+      url: self.link.clone(),
+      external_url: None,
+      title: self.title.clone(),
+      // JSON Feed items need some content, so the description stands in for it if there is none
+      content_html: self.content_encoded.clone().or(self.description.clone()),
+      content_text: None,
+      summary: self.description.clone(),
+      image: self.imageurl.clone(),
+      date_published: self.get_published_timestamp().or(self.timestamp).and_then(dates::format_rfc3339),
+      date_modified: self.get_updated_timestamp().and_then(dates::format_rfc3339),
+      author: None,
+      authors: author.into_iter().collect(),
+      attachments: self.media_content.iter().flatten().map(|media| JsonFeedAttachment {
+        url: media.url.clone(),
+        mime_type: media.mime_type.clone(),
+        title: media.description.clone(),
+        size_in_bytes: media.file_size.as_deref().and_then(|size| size.parse().ok()),
+        duration_in_seconds: None
+      }).collect()
+    }
+  }
+  /// The fields that differ between this item and a newer copy of it
+  pub fn diff(&self, new_item: &Item) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    diff_field(&mut changes, "title", &self.title, &new_item.title);
+    diff_field(&mut changes, "plainTitle", &self.plain_title, &new_item.plain_title);
+    diff_field(&mut changes, "description", &self.description, &new_item.description);
+    diff_field(&mut changes, "imageurl", &self.imageurl, &new_item.imageurl);
+    diff_field(&mut changes, "content:encoded", &self.content_encoded, &new_item.content_encoded);
+    diff_field(&mut changes, "media:content", &get_media_urls(&self.media_content), &get_media_urls(&new_item.media_content));
+    diff_field(&mut changes, "updateDate", &self.update_date, &new_item.update_date);
+    diff_field(&mut changes, "pubDate", &self.pub_date, &new_item.pub_date);
+    diff_field(&mut changes, "createDate", &self.create_date, &new_item.create_date);
+    changes
+  }
+  /// Whether the title or content of the item has changed since it was first fetched
+  pub fn was_materially_updated(&self) -> bool {
+    self.revisions.iter().any(|revision| revision.changes.iter().any(|change| change.is_material()))
+  }
+  /// Replaces the item with a newer copy of it, keeping what changed as a revision
+  /// (at most `max_revisions` of them, dropping the oldest first). Returns whether
+  /// anything changed; the item is left alone if not
+  pub fn update(&mut self, new_item: Item, fetched_at: &str, max_revisions: usize) -> bool {
+    let changes = self.diff(&new_item);
+    // the diff only has the media urls, so compare the rest of the media directly
+    if changes.is_empty() && self.media_content == new_item.media_content {
+      return false;
+    }
+    if !changes.is_empty() && max_revisions > 0 {
+      self.revisions.push(ItemRevision { fetched_at: fetched_at.to_string(), changes });
+      if self.revisions.len() > max_revisions {
+        self.revisions.drain(0..self.revisions.len() - max_revisions);
+      }
+    }
     self.title = new_item.title;
     self.plain_title = new_item.plain_title;
     self.description = new_item.description;
@@ -101,35 +252,152 @@ impl Item {
     self.update_date = new_item.update_date;
     self.pub_date = new_item.pub_date;
     self.create_date = new_item.create_date;
+    self.timestamp = new_item.timestamp;
+    true
   }
 }
 
-pub trait CombineItemLists {
-  fn update_list_by_guids(&mut self, new_items: Vec::<Item>);
+/// How many of the items in a freshly fetched copy of a feed were new, changed or the same
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct MergeReport {
+  pub new: usize,
+  pub updated: usize,
+  pub unchanged: usize
 }
 
-impl CombineItemLists for Vec::<Item> {
-  fn update_list_by_guids(&mut self, new_items: Vec::<Item>) {
-    for new_item in new_items {
-      let mut is_new = true;
-      for item in &mut *self {
-        if new_item.guid == item.guid {
-          is_new = false;
-          item.update(new_item.clone());
-        }
+impl std::fmt::Display for MergeReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{} new, {} updated, {} unchanged", self.new, self.updated, self.unchanged)
+  }
+}
+
+/// The items of a feed in the order they were first fetched, indexed by guid.
+/// It is stored (and parsed from feeds) as a plain list of items.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(from = "Vec<Item>")]
+pub struct ItemList {
+  items: Vec<Item>,
+  // guid -> position in `items`
+  index: HashMap<String, usize>
+}
+
+impl From<Vec<Item>> for ItemList {
+  fn from(items: Vec<Item>) -> Self {
+    let mut item_list = ItemList { items: vec![], index: HashMap::new() };
+    item_list.extend(items);
+    item_list
+  }
+}
+
+impl From<ItemList> for Vec<Item> {
+  fn from(item_list: ItemList) -> Self {
+    item_list.items
+  }
+}
+
+// by hand rather than with `into`, which would clone every item (and its history) first
+impl Serialize for ItemList {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(self.items.iter())
+  }
+}
+
+impl PartialEq for ItemList {
+  fn eq(&self, other: &Self) -> bool {
+    self.items == other.items
+  }
+}
+
+impl std::ops::Deref for ItemList {
+  type Target = [Item];
+  fn deref(&self) -> &[Item] {
+    &self.items
+  }
+}
+
+// items can be changed in place, just not their guids, since the index wouldn't follow
+impl std::ops::DerefMut for ItemList {
+  fn deref_mut(&mut self) -> &mut [Item] {
+    &mut self.items
+  }
+}
+
+impl IntoIterator for ItemList {
+  type Item = Item;
+  type IntoIter = std::vec::IntoIter<Item>;
+  fn into_iter(self) -> Self::IntoIter {
+    self.items.into_iter()
+  }
+}
+
+impl Extend<Item> for ItemList {
+  fn extend<T: IntoIterator<Item = Item>>(&mut self, items: T) {
+    for item in items {
+      self.push(item);
+    }
+  }
+}
+
+impl ItemList {
+  pub fn get_by_guid(&self, guid: &str) -> Option<&Item> {
+    self.index.get(guid).map(|position| &self.items[*position])
+  }
+  /// Adds an item to the end of the list, or replaces the item with the same guid
+  pub fn push(&mut self, item: Item) {
+    match self.index.get(&item.guid) {
+      Some(position) => {
+        self.items[*position] = item;
+      },
+      None => {
+        self.index.insert(item.guid.clone(), self.items.len());
+        self.items.push(item);
       }
-      if is_new {
-        self.push(new_item);
+    }
+  }
+  /// Removes the items at the given (sorted) positions and returns them
+  pub fn remove_positions(&mut self, positions: &[usize]) -> Vec<Item> {
+    let mut removed = Vec::new();
+    let items = std::mem::take(&mut self.items);
+    self.index.clear();
+    for (position, item) in items.into_iter().enumerate() {
+      if positions.binary_search(&position).is_ok() {
+        removed.push(item);
+      } else {
+        self.push(item);
+      }
+    }
+    removed
+  }
+  /// Merges a freshly fetched list of items into the stored one: new guids are added
+  /// to the end and items that changed are updated in place
+  pub fn merge(&mut self, new_items: Vec<Item>, fetched_at: &str, max_revisions: usize) -> MergeReport {
+    let mut report = MergeReport::default();
+    for mut new_item in new_items {
+      new_item.normalize_timestamp();
+      match self.index.get(&new_item.guid) {
+        Some(position) => {
+          if self.items[*position].update(new_item, fetched_at, max_revisions) {
+            report.updated += 1;
+          } else {
+            report.unchanged += 1;
+          }
+        },
+        None => {
+          self.push(new_item);
+          report.new += 1;
+        }
       }
     }
+    report
   }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
 pub struct Channel {
   pub title: String,
   pub link: String,
-  pub item: Vec<Item>,
+  pub item: ItemList,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -146,20 +414,172 @@ pub struct Rss {
   pub content: Option<String>
 }
 
+impl Rss {
+  pub fn new(channel: Channel) -> Rss {
+    Rss {
+      channel,
+      version: Some(String::from("2.0")),
+      webfeeds: Some(String::from("http://webfeeds.org/rss/1.0")),
+      media: Some(String::from("http://search.yahoo.com/mrss/")),
+      content: Some(String::from("http://purl.org/rss/1.0/modules/content/"))
+    }
+  }
+}
+
+/// The settings of a single feed; these live in the config file
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
-pub struct FeedOptions {
-  pub rss: Rss,
+#[serde(default)]
+pub struct FeedSettings {
+  // override title of feed (the feed's own title is used if empty)
+  pub title: String,
+  // override link to feed (the feed's own link is used if empty)
+  pub link: String,
   // cmd to pass input into and accept output out of
   pub manipulate_input: String,
-  pub retain_all_entries: bool,
-  pub title: String,
-  pub link: String
+  // which of the fetched items are kept, applied after every fetch
+  pub retention: RetentionPolicy,
+  // used to pick out groups of feeds with `fetch --tag <tag>`
+  pub tags: Vec<String>,
+  // which feed's copy of a duplicate item wins with `dedup_winner = "feed_priority"`, highest first
+  pub priority: i32,
+  // the name of the feed's own output with `output_one_channel = false`, EX: `art` for
+  // `art.xml`; a slug of the feed's title if empty
+  pub output_name: String
+}
+
+impl Default for FeedSettings {
+  fn default() -> Self {
+    FeedSettings {
+      title: String::from(""),
+      link: String::from(""),
+      manipulate_input: String::from(""),
+      retention: RetentionPolicy::KeepAll,
+      tags: vec![],
+      priority: 0,
+      output_name: String::from("")
+    }
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
-pub struct Db {
-  pub rss: HashMap::<String, FeedOptions>,
+#[serde(default)]
+pub struct FeedOptions {
+  pub rss: Rss,
+  // filled in from the config file; only ever read from the db to migrate
+  // dbs from before the settings were moved out of it
+  #[serde(flatten, skip_serializing)]
+  pub settings: FeedSettings,
+  // set when the feed is stored but its table was taken out of the config file (or its url
+  // mistyped there); it is kept as it is, but not fetched or output until it's back
+  #[serde(skip)]
+  pub missing_from_config: bool,
+  // validators from the last successful response, sent back as
+  // `If-None-Match` and `If-Modified-Since` on the next fetch
+  pub etag: Option<String>,
+  pub last_modified: Option<String>,
+  // the manipulate_input the feed was last parsed with, see `forget_stale_validators`
+  pub validators_manipulate_input: String,
+  // the character encoding the feed was last sent in, shown by `show`
+  pub encoding: Option<String>,
+  // when the feed was last fetched and what went wrong if it failed,
+  // shown by `list` and `show`
+  pub last_fetched: Option<String>,
+  pub last_error: Option<String>,
+  // the guids in the last successfully fetched copy of the feed, used by
+  // `RetentionPolicy::MirrorUpstream`
+  pub upstream_guids: Vec<String>,
+  // guids of stored items that disappeared from the feed while they were still
+  // recent enough to be in it (so were most likely deleted), and when that was noticed
+  pub tombstones: HashMap<String, String>
+}
+
+impl Default for FeedOptions {
+  fn default() -> Self {
+    FeedOptions {
+      rss: Rss::new(Channel {
+        title: String::from(""),
+        link: String::from(""),
+        item: ItemList::default()
+      }),
+      settings: FeedSettings::default(),
+      missing_from_config: false,
+      etag: None,
+      last_modified: None,
+      validators_manipulate_input: String::from(""),
+      encoding: None,
+      last_fetched: None,
+      last_error: None,
+      upstream_guids: vec![],
+      tombstones: HashMap::new()
+    }
+  }
+}
+
+impl FeedOptions {
+  pub fn title(&self) -> &str {
+    if self.settings.title.is_empty() {
+      &self.rss.channel.title
+    } else {
+      &self.settings.title
+    }
+  }
+  pub fn link(&self) -> &str {
+    if self.settings.link.is_empty() {
+      &self.rss.channel.link
+    } else {
+      &self.settings.link
+    }
+  }
+  /// Drops the validators when manipulate_input changed since they were stored, otherwise
+  /// the feed would answer 304 and the new command wouldn't be applied until it changes
+  pub fn forget_stale_validators(&mut self) {
+    if self.validators_manipulate_input != self.settings.manipulate_input {
+      self.etag = None;
+      self.last_modified = None;
+    }
+  }
+  /// Merges a freshly fetched copy of the feed into the stored one, keeping at
+  /// most `max_revisions` earlier versions of each item that changed
+  pub fn merge(&mut self, rss: Rss, fetched_at: &str, max_revisions: usize) -> MergeReport {
+    self.upstream_guids = rss.channel.item.iter().map(|item| item.guid.clone()).collect();
+    self.update_tombstones(&rss.channel.item, fetched_at);
+    let report = self.rss.channel.item.merge(rss.channel.item.into(), fetched_at, max_revisions);
+    self.rss.channel.title = rss.channel.title;
+    self.rss.channel.link = rss.channel.link;
+    report
+  }
+  // feeds only ever hold their newest items, so an item missing from the feed is only
+  // counted as deleted when it is newer than the oldest item the feed still has
+  fn update_tombstones(&mut self, upstream_items: &[Item], fetched_at: &str) {
+    let upstream_guids = upstream_items.iter().map(|item| &item.guid).collect::<std::collections::HashSet<_>>();
+    // anything that came back wasn't deleted after all
+    self.tombstones.retain(|guid, _| !upstream_guids.contains(guid));
+    // the upstream items haven't been through `normalize_timestamp` yet, unlike the stored ones
+    let window_start = match upstream_items.iter().filter_map(|item| item.get_normalized_timestamp()).min() {
+      Some(window_start) => window_start,
+      // an empty feed (or one without dates) says nothing about what was deleted
+      None => return
+    };
+    for item in self.rss.channel.item.iter() {
+      if upstream_guids.contains(&item.guid) || self.tombstones.contains_key(&item.guid) {
+        continue;
+      }
+      if item.timestamp.is_some_and(|timestamp| timestamp >= window_start) {
+        self.tombstones.insert(item.guid.clone(), fetched_at.to_string());
+      }
+    }
+  }
+}
+
+/// The settings for the aggregated feed as a whole; these live in the config file
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct Settings {
+  // when false every feed is also output on its own (see `FeedSettings::output_name`)
   pub output_one_channel: bool,
+  // whether the combined feed is still output alongside the per feed ones when
+  // `output_one_channel` is false
+  pub output_combined_feed: bool,
   pub title: String,
   pub link: String,
   // useful for mastodon because mastodon doesn't populate the title
@@ -172,14 +592,36 @@ pub struct Db {
   pub populate_content_encoded: bool,
   pub add_media_to_content_encoded: bool,
   pub max_entries_published: i32,
-  pub override_item_author: bool
+  pub override_item_author: bool,
+  // how many feeds are fetched at the same time
+  pub max_concurrent_fetches: usize,
+  // how many feeds are fetched at the same time from any one host
+  pub max_concurrent_fetches_per_host: usize,
+  // how many seconds a single fetch may take before it is given up on
+  pub fetch_timeout: u64,
+  // how many previous versions of db.json are kept around as db.json.1, db.json.2, etc.
+  pub db_backups: usize,
+  // whether items deleted from the feed they came from are left out of the output
+  pub suppress_tombstoned_items: bool,
+  // how many earlier versions of each item are kept when a fetch changes it
+  pub max_revisions_per_item: usize,
+  // appended to the title of items whose title or content changed after they were
+  // first fetched; nothing is added if empty
+  pub updated_marker: String,
+  // how items posted to more than one feed are recognized as the same post (none turns it off)
+  pub dedup_by: Vec<DedupStrategy>,
+  // which copy of a duplicate item is output
+  pub dedup_winner: DedupWinner,
+  // a regex matching a note in a post that says it is a copy of another post; the first
+  // group (or the whole match) is the link to the original
+  pub cross_post_marker: String
 }
 
-impl Db {
-  pub fn new() -> Db {
-    Db {
-      rss: HashMap::<String, FeedOptions>::new(),
+impl Default for Settings {
+  fn default() -> Self {
+    Settings {
       output_one_channel: true,
+      output_combined_feed: true,
       title: String::from(""),
       link: String::from(""),
       include_description_as_title_if_none_given: true,
@@ -188,35 +630,139 @@ impl Db {
       populate_content_encoded: true,
       add_media_to_content_encoded: true,
       max_entries_published: -1,// -1 is max
-      override_item_author: false
+      override_item_author: false,
+      max_concurrent_fetches: 8,
+      max_concurrent_fetches_per_host: 2,
+      fetch_timeout: 30,
+      db_backups: 3,
+      suppress_tombstoned_items: false,
+      max_revisions_per_item: 10,
+      updated_marker: String::from(""),
+      dedup_by: vec![],
+      dedup_winner: DedupWinner::LongestContent,
+      cross_post_marker: String::from("")
     }
   }
-  pub fn output_rss(&self) -> Result<std::string::String, quick_xml::DeError> {
-    let mut items = Vec::<Item>::new();
-    for (_, feed_options) in self.rss.clone() {
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct Db {
+  // which version of the format the db was written with, see `migrations`;
+  // dbs from before it was added are version 0
+  pub schema_version: u64,
+  pub rss: HashMap::<String, FeedOptions>,
+  // filled in from the config file; only ever read from the db to migrate
+  // dbs from before the settings were moved out of it
+  #[serde(flatten, skip_serializing)]
+  pub settings: Settings
+}
+
+impl Default for Db {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Db {
+  pub fn new() -> Db {
+    Db {
+      schema_version: crate::migrations::CURRENT_SCHEMA_VERSION,
+      rss: HashMap::<String, FeedOptions>::new(),
+      settings: Settings::default()
+    }
+  }
+  /// The db cut down to a single feed, with the feed's own title and link as the title
+  /// and link of the output
+  pub fn for_feed(&self, url: &str) -> Option<Db> {
+    let feed_options = self.rss.get(url)?;
+    let mut settings = self.settings.clone();
+    settings.title = feed_options.title().to_string();
+    settings.link = feed_options.link().to_string();
+    Some(Db {
+      schema_version: self.schema_version,
+      rss: HashMap::from([(url.to_string(), feed_options.clone())]),
+      settings
+    })
+  }
+  /// Every feed cut down to a db of its own (see `for_feed`), along with the name its
+  /// output goes under: a slug of its `output_name`, or of its title (or URL). Names are
+  /// made unique by adding `-2`, `-3` and so on.
+  pub fn per_feed_outputs(&self) -> Vec<(String, Db)> {
+    let mut urls = self.rss.keys().collect::<Vec<_>>();
+    urls.sort();
+    let mut taken = std::collections::HashSet::new();
+    let mut outputs = Vec::new();
+    for url in urls {
+      let feed_options = &self.rss[url];
+      if feed_options.missing_from_config {
+        continue;
+      }
+      // the name ends up in a path, so it can't be allowed to point anywhere else
+      let output_name_slug = slugify(&feed_options.settings.output_name);
+      if output_name_slug != feed_options.settings.output_name {
+        log::warn!("⚠️ {url}: output_name `{}` can only have lowercase letters, digits and dashes, so `{}` is used", feed_options.settings.output_name, output_name_slug);
+      }
+      let title_slug = slugify(feed_options.title());
+      let name = if !output_name_slug.is_empty() {
+        output_name_slug
+      } else if !title_slug.is_empty() {
+        title_slug
+      } else {
+        slugify(url.trim_start_matches("https://").trim_start_matches("http://"))
+      };
+      let mut unique_name = name.clone();
+      let mut count = 1;
+      while taken.contains(&unique_name) {
+        count += 1;
+        unique_name = format!("{}-{}", name, count);
+      }
+      if unique_name != name {
+        log::warn!("⚠️ {url}: `{name}` is already taken, so it is output as `{unique_name}`");
+      }
+      taken.insert(unique_name.clone());
+      if let Some(db) = self.for_feed(url) {
+        outputs.push((unique_name, db));
+      }
+    }
+    outputs
+  }
+  /// The items of every feed as they go into any of the outputs: with authors, titles
+  /// and `content:encoded` filled in per the settings, without duplicates, newest first
+  /// and cut down to `max_entries_published`
+  pub fn prepare_items(&self) -> Vec<Item> {
+    let mut items = Vec::<(Item, String, i32)>::new();
+    let tag_regex = regex::Regex::new(r#"<[^>]*>"#).unwrap();
+    for (url, feed_options) in self.rss.clone() {
+      if feed_options.missing_from_config {
+        continue;
+      }
+      let feed_author = Author {
+        name: feed_options.title().to_string(),
+        uri: feed_options.link().to_string()
+      };
       for mut item in feed_options.rss.channel.item {
-        if item.author.is_none() || self.override_item_author {
-          item.author = Some(Author {
-            name: feed_options.title.clone(),
-            uri: feed_options.link.clone()
-          });
+        if self.settings.suppress_tombstoned_items && feed_options.tombstones.contains_key(&item.guid) {
+          continue;
         }
-        if item.title.is_none() && self.include_description_as_title_if_none_given {
+        if item.author.is_none() || self.settings.override_item_author {
+          item.author = Some(feed_author.clone());
+        }
+        if item.title.is_none() && self.settings.include_description_as_title_if_none_given {
           item.title = item.description.clone().map(|d| {
-            let r = regex::Regex::new(r#"<[^>]*>"#).unwrap();
-            let d_text = r.replace_all(&d, "").replace("&#39;", "'");
+            let d_text = tag_regex.replace_all(&d, "").replace("&#39;", "'");
             let parts = d_text.split(" ").collect::<Vec::<&str>>();
-            if parts.len() > self.description_title_word_count as usize {
-              format!("{}{}", parts[0..self.description_title_word_count as usize].join(" ").trim(), self.title_ellipsis)
+            if parts.len() > self.settings.description_title_word_count as usize {
+              format!("{}{}", parts[0..self.settings.description_title_word_count as usize].join(" ").trim(), self.settings.title_ellipsis)
             } else {
               d_text
             }
           });
         }
-        if item.content_encoded.is_none() && self.populate_content_encoded {
+        if item.content_encoded.is_none() && self.settings.populate_content_encoded {
           item.content_encoded = item.description.clone();
         }
-        if item.content_encoded.is_some() && self.add_media_to_content_encoded {
+        if item.content_encoded.is_some() && self.settings.add_media_to_content_encoded {
           let content_encoded = item.content_encoded.clone().unwrap();
           item.content_encoded = Some(format!("{}<br>{}", content_encoded, item.media_content.clone().map(|mc| {
             mc.into_iter().filter_map(|c| {
@@ -228,12 +774,18 @@ impl Db {
             }).collect::<Vec<_>>().join(" ")
           }).unwrap_or("".to_owned())));
         }
-        items.push(item);
+        if !self.settings.updated_marker.is_empty() && item.was_materially_updated() {
+          item.title = item.title.map(|title| format!("{} {}", title, self.settings.updated_marker));
+        }
+        // the history stays in the db
+        item.revisions = vec![];
+        items.push((item, url.clone(), feed_options.settings.priority));
       }
     }
+    let mut items = dedup::dedup_items(items, &self.settings.dedup_by, self.settings.dedup_winner, &self.settings.cross_post_marker);
     items.sort_by(|a, b| {
-      let atime = a.get_published_timestamp();
-      let btime = b.get_published_timestamp();
+      let atime = a.timestamp;
+      let btime = b.timestamp;
       if atime > btime { 
         Ordering::Less
       } else if atime < btime {
@@ -242,22 +794,74 @@ impl Db {
         Ordering::Equal
       }
     });
-    if self.max_entries_published > 0 {
-      items = items[0..self.max_entries_published as usize].to_vec()
-    }
-    let new_rss = Rss {
-      channel: Channel {
-        title: self.title.clone(),
-        link: self.link.clone(),
-        item: items
-      },
-      version: Some(String::from("2.0")),
-      webfeeds: Some(String::from("http://webfeeds.org/rss/1.0")),
-      media: Some(String::from("http://search.yahoo.com/mrss/")),
-      content: Some(String::from("http://purl.org/rss/1.0/modules/content/"))
-    };
+    if self.settings.max_entries_published > 0 {
+      items.truncate(self.settings.max_entries_published as usize);
+    }
+    items
+  }
+  pub fn output_rss(&self) -> Result<std::string::String, quick_xml::DeError> {
+    let mut items = self.prepare_items();
+    // only needed for sorting
+    for item in items.iter_mut() {
+      item.timestamp = None;
+    }
+    let new_rss = Rss::new(Channel {
+      title: self.settings.title.clone(),
+      link: self.settings.link.clone(),
+      item: items.into()
+    });
     quick_xml::se::to_string(&new_rss)
   }
+  /// The aggregated feed as JSON Feed 1.1; `feed_url` is where the file will be published
+  pub fn output_json_feed(&self, feed_url: Option<String>) -> Result<std::string::String, serde_json::Error> {
+    let json_feed = JsonFeed {
+      version: String::from("https://jsonfeed.org/version/1.1"),
+      title: self.settings.title.clone(),
+      home_page_url: Some(self.settings.link.clone()).filter(|link| !link.is_empty()),
+      feed_url,
+      description: None,
+      author: None,
+      authors: vec![],
+      items: self.prepare_items().iter().map(|item| item.into_json_feed_item()).collect()
+    };
+    serde_json::to_string_pretty(&json_feed)
+  }
+  /// The aggregated feed as Atom 1.0; `self_link` is where the file will be published
+  /// The items as an Atom feed; `output_file_name` is only used for the feed's id when it
+  /// has neither a `link` nor a `self_link`
+  pub fn output_atom(&self, self_link: Option<String>, output_file_name: &str) -> Result<std::string::String, quick_xml::DeError> {
+    let entries = self.prepare_items().iter().map(|item| item.into_atom_entry()).collect::<Vec<_>>();
+    let updated = entries.iter()
+      .map(|entry| entry.updated.clone())
+      .max()
+      .unwrap_or(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+    let mut link = vec![];
+    if !self.settings.link.is_empty() {
+      link.push(Link { rel: String::from("alternate"), href: self.settings.link.clone(), mime_type: None, length: None });
+    }
+    if let Some(self_link) = &self_link {
+      link.push(Link { rel: String::from("self"), href: self_link.clone(), mime_type: Some(String::from("application/atom+xml")), length: None });
+    }
+    // the id has to stay the same between runs (and can't be empty), so it goes by the
+    // link, the self link or else the name of the file the feed is written to
+    let id = if !self.settings.link.is_empty() {
+      self.settings.link.clone()
+    } else if let Some(self_link) = self_link {
+      self_link
+    } else {
+      let file_name = std::path::Path::new(output_file_name).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+      format!("urn:syndication-junction:{}", urlencoding::encode(&file_name))
+    };
+    let feed = AtomFeed {
+      xmlns: String::from("http://www.w3.org/2005/Atom"),
+      id,
+      title: self.settings.title.clone(),
+      updated,
+      link,
+      entry: entries
+    };
+    quick_xml::se::to_string(&feed)
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -265,7 +869,11 @@ pub struct Link {
   #[serde(rename = "@rel")]
   pub rel: String,
   #[serde(rename = "@href")]
-  pub href: String
+  pub href: String,
+  #[serde(rename = "@type", default, skip_serializing_if = "Option::is_none")]
+  pub mime_type: Option<String>,
+  #[serde(rename = "@length", default, skip_serializing_if = "Option::is_none")]
+  pub length: Option<String>
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -296,15 +904,13 @@ impl Entry {
       update_date: pub_date.clone(),
       media_content: self.media_content.clone(),
       content_encoded: Some(self.content.clone()),
-      author: Some(self.author.clone())
+      author: Some(self.author.clone()),
+      revisions: vec![],
+      timestamp: None
     }
   }
   pub fn get_updated_time_as_item_format(&self) -> Option<String> {
-    let ts = get_timestamp_from_string(Some(self.updated.clone()), "%Y-%m-%dT%T%.3fZ");
-    ts.map(|timestamp| {
-      let datetime = chrono::Utc.timestamp_opt(timestamp, 0).unwrap();
-      datetime.format("%a, %d %h %Y %H:%M:%S %z").to_string()
-    })
+    dates::parse_timestamp(&self.updated).and_then(dates::format_rfc2822)
   }
 }
 
@@ -326,16 +932,475 @@ pub struct Feed {
 
 impl Feed {
   pub fn into_rss(&self) -> Rss {
-    Rss {
-      channel: Channel {
-        title: self.title.clone(),
-        link: self.author.uri.clone(),
-        item: self.entry.clone().unwrap_or(vec![]).into_iter().map(|e| e.into_item()).collect()
-      },
-      version: Some(String::from("2.0")),
-      webfeeds: Some(String::from("http://webfeeds.org/rss/1.0")),
-      media: Some(String::from("http://search.yahoo.com/mrss/")),
-      content: Some(String::from("http://purl.org/rss/1.0/modules/content/"))
+    Rss::new(Channel {
+      title: self.title.clone(),
+      link: self.author.uri.clone(),
+      item: self.entry.clone().unwrap_or(vec![]).into_iter().map(|e| e.into_item()).collect::<Vec<_>>().into()
+    })
+  }
+}
+
+/// Text in an Atom feed that is marked up as HTML
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AtomHtml {
+  #[serde(rename = "@type")]
+  pub kind: String,
+  #[serde(rename = "$text")]
+  pub value: String
+}
+
+impl AtomHtml {
+  pub fn new(value: String) -> AtomHtml {
+    AtomHtml { kind: String::from("html"), value }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AtomPerson {
+  pub name: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  pub uri: String
+}
+
+/// An entry of the Atom feed written by `output-atom`; `Entry` is what is read from
+/// Atom feeds, which is a lot more lenient about what may be left out
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AtomEntry {
+  pub id: String,
+  pub title: String,
+  pub updated: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub published: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub author: Option<AtomPerson>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub link: Vec<Link>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub summary: Option<AtomHtml>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub content: Option<AtomHtml>
+}
+
+/// The Atom feed written by `output-atom`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename = "feed")]
+pub struct AtomFeed {
+  #[serde(rename = "@xmlns")]
+  pub xmlns: String,
+  pub id: String,
+  pub title: String,
+  pub updated: String,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub link: Vec<Link>,
+  #[serde(default)]
+  pub entry: Vec<AtomEntry>
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct RdfChannel {
+  pub title: String,
+  pub link: String,
+  pub description: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct RdfItem {
+  #[serde(rename = "@rdf:about")]
+  pub about: Option<String>,
+  pub title: Option<String>,
+  pub link: String,
+  pub description: Option<String>,
+  #[serde(alias = "dc:date", rename(serialize = "dc:date", deserialize = "dc-date"))]
+  pub date: Option<String>,
+  #[serde(alias = "dc:creator", rename(serialize = "dc:creator", deserialize = "dc-creator"))]
+  pub creator: Option<String>,
+  #[serde(alias = "content:encoded", rename(serialize = "content:encoded", deserialize = "content-encoded"))]
+  pub content_encoded: Option<String>
+}
+
+impl RdfItem {
+  pub fn into_item(&self) -> Item {
+    // dc:date is ISO 8601, which RSS readers don't expect in pubDate
+    let pub_date = self.date.as_deref()
+      .and_then(dates::parse_timestamp)
+      .and_then(dates::format_rfc2822)
+      .or(self.date.clone());
+    Item {
+      guid: self.about.clone().unwrap_or(self.link.clone()),
+      title: self.title.clone(),
+      plain_title: self.title.clone(),
+      imageurl: None,
+      link: Some(self.link.clone()),
+      description: self.description.clone(),
+      author: self.creator.clone().map(|creator| Author {
+        name: creator,
+        uri: String::from("")
+      }),
+      pub_date,
+      create_date: None,
+      update_date: None,
+      media_content: None,
+      content_encoded: self.content_encoded.clone(),
+      revisions: vec![],
+      timestamp: None
     }
   }
 }
+
+/// An RSS 1.0 feed, where the items are siblings of the channel instead of inside it
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename = "rdf:RDF")]
+pub struct Rdf {
+  pub channel: RdfChannel,
+  #[serde(default)]
+  pub item: Vec<RdfItem>
+}
+
+impl Rdf {
+  pub fn into_rss(&self) -> Rss {
+    Rss::new(Channel {
+      title: self.channel.title.clone(),
+      link: self.channel.link.clone(),
+      item: self.item.iter().map(|item| item.into_item()).collect::<Vec<_>>().into()
+    })
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct JsonFeedAuthor {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub url: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub avatar: Option<String>
+}
+
+impl JsonFeedAuthor {
+  pub fn into_author(&self) -> Author {
+    Author {
+      name: self.name.clone().unwrap_or_default(),
+      uri: self.url.clone().unwrap_or_default()
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct JsonFeedAttachment {
+  pub url: String,
+  pub mime_type: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub size_in_bytes: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub duration_in_seconds: Option<f64>
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct JsonFeedItem {
+  // meant to be a string, but JSON Feed 1.0 feeds out there use numbers too
+  pub id: serde_json::Value,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub url: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub external_url: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub content_html: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub content_text: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub summary: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub image: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub date_published: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub date_modified: Option<String>,
+  // `author` is from JSON Feed 1.0, `authors` replaced it in 1.1
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub author: Option<JsonFeedAuthor>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub authors: Vec<JsonFeedAuthor>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub attachments: Vec<JsonFeedAttachment>
+}
+
+// JSON Feed dates are RFC 3339, which RSS readers don't expect
+fn get_json_feed_date_as_item_format(date: &Option<String>) -> Option<String> {
+  date.as_deref()
+    .and_then(dates::parse_timestamp)
+    .and_then(dates::format_rfc2822)
+    .or(date.clone())
+}
+
+impl JsonFeedItem {
+  pub fn into_item(&self) -> Item {
+    let guid = match &self.id {
+      serde_json::Value::String(id) => id.clone(),
+      id => id.to_string()
+    };
+    let media_content = if self.attachments.is_empty() {
+      None
+    } else {
+      Some(self.attachments.iter().map(|attachment| {
+        MediaContent::new(
+          attachment.url.clone(),
+          attachment.mime_type.clone(),
+          attachment.size_in_bytes.map(|size| size.to_string()),
+          attachment.title.clone()
+        )
+      }).collect::<Vec<_>>())
+    };
+    Item {
+      guid,
+      title: self.title.clone(),
+      plain_title: self.title.clone(),
+      imageurl: self.image.clone(),
+      link: self.url.clone().or(self.external_url.clone()),
+      description: self.summary.clone().or(self.content_html.clone()).or(self.content_text.clone()),
+      author: self.authors.first().or(self.author.as_ref()).map(|author| author.into_author()),
+      pub_date: get_json_feed_date_as_item_format(&self.date_published),
+      create_date: None,
+      update_date: get_json_feed_date_as_item_format(&self.date_modified),
+      media_content,
+      content_encoded: self.content_html.clone(),
+      revisions: vec![],
+      timestamp: None
+    }
+  }
+}
+
+/// A JSON Feed (https://www.jsonfeed.org/version/1.1/), as read from feeds and as
+/// written by `output-json-feed`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct JsonFeed {
+  pub version: String,
+  pub title: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub home_page_url: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub feed_url: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub author: Option<JsonFeedAuthor>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub authors: Vec<JsonFeedAuthor>,
+  #[serde(default)]
+  pub items: Vec<JsonFeedItem>
+}
+
+impl JsonFeed {
+  pub fn into_rss(&self) -> Rss {
+    // items without authors of their own are by the authors of the feed
+    let feed_author = self.authors.first().or(self.author.as_ref()).map(|author| author.into_author());
+    Rss::new(Channel {
+      title: self.title.clone(),
+      link: self.home_page_url.clone().or(self.feed_url.clone()).unwrap_or_default(),
+      item: self.items.iter().map(|item| {
+        let mut item = item.into_item();
+        if item.author.is_none() {
+          item.author = feed_author.clone();
+        }
+        item
+      }).collect::<Vec<_>>().into()
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helpers::*;
+
+  fn rss(items: Vec<Item>) -> Rss {
+    Rss::new(Channel { title: String::from("Feed"), link: String::from("https://feed.example/"), item: items.into() })
+  }
+
+  // an item published on the given day of August 2023
+  fn dated(guid: &str, day: u32) -> Item {
+    item(guid, Some(&format!("{day} Aug 2023 18:00:00 +0000")))
+  }
+
+  fn feed_with(items: Vec<Item>) -> FeedOptions {
+    let mut options = FeedOptions::default();
+    options.merge(rss(items), "first fetch", 0);
+    options
+  }
+
+  #[test]
+  fn tombstones_items_deleted_within_the_window() {
+    let mut options = feed_with(vec![dated("old", 10), dated("kept", 14), dated("deleted", 15), dated("newest", 16)]);
+    // the feed now starts at the 14th, so `old` just scrolled out of it but `deleted` is gone
+    options.merge(rss(vec![dated("kept", 14), dated("newest", 16)]), "second fetch", 0);
+    assert_eq!(options.tombstones, HashMap::from([(String::from("deleted"), String::from("second fetch"))]));
+    // the items themselves are kept
+    assert_eq!(options.rss.channel.item.len(), 4);
+  }
+
+  #[test]
+  fn keeps_the_first_tombstone_and_clears_it_when_the_item_comes_back() {
+    let mut options = feed_with(vec![dated("a", 14), dated("b", 15), dated("c", 16)]);
+    options.merge(rss(vec![dated("a", 14), dated("c", 16)]), "second fetch", 0);
+    options.merge(rss(vec![dated("a", 14), dated("c", 16)]), "third fetch", 0);
+    assert_eq!(options.tombstones.get("b").map(String::as_str), Some("second fetch"));
+    options.merge(rss(vec![dated("a", 14), dated("b", 15), dated("c", 16)]), "fourth fetch", 0);
+    assert!(options.tombstones.is_empty());
+  }
+
+  #[test]
+  fn empty_or_undated_feeds_tombstone_nothing() {
+    let mut options = feed_with(vec![dated("a", 14), dated("b", 15)]);
+    options.merge(rss(vec![]), "second fetch", 0);
+    options.merge(rss(vec![item("undated", None)]), "third fetch", 0);
+    assert!(options.tombstones.is_empty());
+  }
+
+  #[test]
+  fn suppresses_tombstoned_items_when_asked() {
+    let mut db = Db::new();
+    let mut options = feed_with(vec![dated("a", 14), dated("b", 15)]);
+    options.merge(rss(vec![dated("a", 14)]), "second fetch", 0);
+    db.rss.insert(String::from("https://feed.example/rss.xml"), options);
+    assert_eq!(guids(&db.prepare_items()), vec!["b", "a"]);
+    db.settings.suppress_tombstoned_items = true;
+    assert_eq!(guids(&db.prepare_items()), vec!["a"]);
+  }
+
+  fn titled(title: &str) -> Item {
+    item_with(serde_json::json!({ "guid": "a", "title": title, "pubDate": "Wed, 16 Aug 2023 18:00:00 +0000" }))
+  }
+
+  #[test]
+  fn keeps_what_changed_as_a_revision() {
+    let mut stored = titled("first");
+    assert!(stored.update(titled("second"), "second fetch", 5));
+    assert_eq!(stored.title.as_deref(), Some("second"));
+    assert_eq!(stored.revisions, vec![ItemRevision {
+      fetched_at: String::from("second fetch"),
+      changes: vec![FieldChange { field: String::from("title"), old: Some(String::from("first")), new: Some(String::from("second")) }]
+    }]);
+    // the same copy again changes nothing
+    assert!(!stored.update(titled("second"), "third fetch", 5));
+    assert_eq!(stored.revisions.len(), 1);
+  }
+
+  #[test]
+  fn caps_the_revisions_dropping_the_oldest() {
+    let mut stored = titled("0");
+    for n in 1..=4 {
+      stored.update(titled(&n.to_string()), &format!("fetch {n}"), 2);
+    }
+    let fetches = stored.revisions.iter().map(|revision| revision.fetched_at.as_str()).collect::<Vec<_>>();
+    assert_eq!(fetches, vec!["fetch 3", "fetch 4"]);
+    assert_eq!(stored.revisions[0].changes[0].old.as_deref(), Some("2"));
+    // with no revisions kept the item is still updated
+    assert!(stored.update(titled("5"), "fetch 5", 0));
+    assert_eq!(stored.title.as_deref(), Some("5"));
+    assert_eq!(stored.revisions.len(), 2);
+  }
+
+  #[test]
+  fn only_marks_material_updates() {
+    let mut stored = titled("first");
+    let mut redated = titled("first");
+    redated.update_date = Some(String::from("Thu, 17 Aug 2023 18:00:00 +0000"));
+    stored.update(redated, "second fetch", 5);
+    assert_eq!(stored.revisions.len(), 1);
+    assert!(!stored.was_materially_updated());
+    stored.update(titled("second"), "third fetch", 5);
+    assert!(stored.was_materially_updated());
+    let mut db = Db::new();
+    db.settings.updated_marker = String::from("(updated)");
+    db.rss.insert(String::from("https://feed.example/rss.xml"), feed_with(vec![stored]));
+    let items = db.prepare_items();
+    assert_eq!(items[0].title.as_deref(), Some("second (updated)"));
+    // the history stays out of the output
+    assert!(items[0].revisions.is_empty());
+  }
+
+  #[test]
+  fn indexes_items_by_guid() {
+    // a repeated guid replaces the earlier item in its place
+    let list = ItemList::from(vec![dated("a", 14), dated("b", 15), dated("a", 16)]);
+    assert_eq!(guids(&list), vec!["a", "b"]);
+    assert_eq!(list.get_by_guid("a").unwrap().pub_date, Some(String::from("16 Aug 2023 18:00:00 +0000")));
+    assert!(list.get_by_guid("c").is_none());
+  }
+
+  #[test]
+  fn reindexes_after_removing_items() {
+    let mut list = ItemList::from(vec![dated("a", 14), dated("b", 15), dated("c", 16), dated("d", 17)]);
+    let removed = list.remove_positions(&[0, 2]);
+    assert_eq!(guids(&removed), vec!["a", "c"]);
+    assert_eq!(guids(&list), vec!["b", "d"]);
+    assert!(list.get_by_guid("a").is_none());
+    assert_eq!(list.get_by_guid("d").unwrap().guid, "d");
+    list.push(dated("e", 18));
+    assert_eq!(list.get_by_guid("e").unwrap().guid, "e");
+  }
+
+  #[test]
+  fn merges_new_updated_and_unchanged_items() {
+    let mut list = ItemList::from(vec![titled("first"), dated("b", 15)]);
+    let report = list.merge(vec![dated("c", 16), dated("b", 15), titled("second")], "second fetch", 5);
+    assert_eq!(report, MergeReport { new: 1, updated: 1, unchanged: 1 });
+    assert_eq!(report.to_string(), "1 new, 1 updated, 1 unchanged");
+    // new items go at the end and changed ones stay where they were
+    assert_eq!(guids(&list), vec!["a", "b", "c"]);
+    assert_eq!(list[0].title.as_deref(), Some("second"));
+    assert_eq!(list.get_by_guid("c").unwrap().timestamp, Some(1692208800));
+  }
+
+  #[test]
+  fn serializes_as_a_plain_list() {
+    let list = ItemList::from(vec![dated("a", 14), dated("b", 15)]);
+    let json = serde_json::to_value(&list).unwrap();
+    assert_eq!(json.as_array().map(Vec::len), Some(2));
+    let read_back = serde_json::from_value::<ItemList>(json).unwrap();
+    assert_eq!(read_back, list);
+    assert_eq!(read_back.get_by_guid("b").unwrap().guid, "b");
+  }
+
+  #[test]
+  fn gives_atom_feeds_a_stable_id() {
+    let mut db = Db::new();
+    let id = |db: &Db, self_link: Option<&str>| {
+      let atom = db.output_atom(self_link.map(String::from), "out/my feed.xml").unwrap();
+      atom.split("<id>").nth(1).and_then(|rest| rest.split("</id>").next()).map(String::from)
+    };
+    assert_eq!(id(&db, None).as_deref(), Some("urn:syndication-junction:my%20feed.xml"));
+    assert_eq!(id(&db, Some("https://host.example/atom.xml")).as_deref(), Some("https://host.example/atom.xml"));
+    db.settings.link = String::from("https://host.example/");
+    assert_eq!(id(&db, Some("https://host.example/atom.xml")).as_deref(), Some("https://host.example/"));
+  }
+
+  #[test]
+  fn names_per_feed_outputs() {
+    let feed = |title: &str, output_name: &str| {
+      let mut options = feed_with(vec![]);
+      options.rss.channel.title = String::new();
+      options.settings.title = String::from(title);
+      options.settings.output_name = String::from(output_name);
+      options
+    };
+    let mut gone = feed("Gone", "");
+    gone.missing_from_config = true;
+    let mut db = Db::new();
+    db.rss = HashMap::from([
+      (String::from("https://a.example/rss.xml"), feed("Emma's Art", "")),
+      (String::from("https://b.example/rss.xml"), feed("Other", "art")),
+      (String::from("https://c.example/rss.xml"), feed("Blog", "sub/x")),
+      // nothing is left of it as a slug, so the title is used
+      (String::from("https://d.example/rss.xml"), feed("Notes", "../..")),
+      (String::from("https://e.example/rss.xml"), feed("Notes", "")),
+      (String::from("https://f.example/rss.xml"), feed("", "")),
+      (String::from("https://g.example/rss.xml"), gone)
+    ]);
+    let names = db.per_feed_outputs().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["emma-s-art", "art", "sub-x", "notes", "notes-2", "f-example-rss-xml"]);
+  }
+}
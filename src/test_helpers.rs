@@ -0,0 +1,31 @@
+// fixtures shared by the unit tests
+use std::path::PathBuf;
+use serde_json::{Value, json};
+
+use crate::structs::*;
+
+/// An item with only the given fields (named as in db.json), with its `timestamp`
+/// filled in the way it is when the item is fetched
+pub fn item_with(fields: Value) -> Item {
+  let mut item = serde_json::from_value::<Item>(fields).unwrap();
+  item.normalize_timestamp();
+  item
+}
+
+/// An item with only a guid and a publish date
+pub fn item(guid: &str, pub_date: Option<&str>) -> Item {
+  item_with(json!({ "guid": guid, "pubDate": pub_date }))
+}
+
+/// The guids of `items`, in order
+pub fn guids(items: &[Item]) -> Vec<&str> {
+  items.iter().map(|item| item.guid.as_str()).collect()
+}
+
+/// An empty directory of its own for a test to write files into
+pub fn temp_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("syndication_junction-{}-{}", std::process::id(), name));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}